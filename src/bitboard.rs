@@ -0,0 +1,338 @@
+use std::sync::LazyLock;
+
+use crate::board::{Board, Color, Piece};
+use crate::zobrist::piece_index;
+
+/* A bitboard is a u64 with one bit per square, bit 0 is a1 and bit 63 is h8 like the mailbox.
+Board keeps one per piece type and color next to `squares`, so questions like "where are the
+white knights" or "what does this rook attack" are a couple of ands and shifts instead of
+walking the board square by square.
+*/
+pub const FILE_A: u64 = 0x0101010101010101;
+pub const FILE_H: u64 = FILE_A << 7;
+pub const RANK_1: u64 = 0xFF;
+pub const RANK_4: u64 = RANK_1 << (8 * 3);
+pub const RANK_5: u64 = RANK_1 << (8 * 4);
+pub const RANK_8: u64 = RANK_1 << (8 * 7);
+
+pub const fn square_bb(square: usize) -> u64 {
+    1 << square
+}
+
+// index of the lowest set bit, and clears it, for looping over the squares of a bitboard
+pub fn pop_lsb(bitboard: &mut u64) -> usize {
+    let square = bitboard.trailing_zeros() as usize;
+    *bitboard &= *bitboard - 1;
+    square
+}
+
+pub struct Squares(u64);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            None
+        } else {
+            Some(pop_lsb(&mut self.0))
+        }
+    }
+}
+
+pub fn squares(bitboard: u64) -> Squares {
+    Squares(bitboard)
+}
+
+pub fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+// the squares reachable with the given (file, rank) steps, stopping at the edge of the board
+const fn step_attacks(square: usize, steps: &[(i32, i32)]) -> u64 {
+    let file = (square % 8) as i32;
+    let rank = (square / 8) as i32;
+    let mut attacks = 0;
+    let mut i = 0;
+    while i < steps.len() {
+        let (new_file, new_rank) = (file + steps[i].0, rank + steps[i].1);
+        if new_file >= 0 && new_file < 8 && new_rank >= 0 && new_rank < 8 {
+            attacks |= 1 << (new_rank * 8 + new_file);
+        }
+        i += 1;
+    }
+    attacks
+}
+
+const fn step_table(steps: &[(i32, i32)]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        table[square] = step_attacks(square, steps);
+        square += 1;
+    }
+    table
+}
+
+const KNIGHT_STEPS: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_STEPS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+pub const KNIGHT_ATTACKS: [u64; 64] = step_table(&KNIGHT_STEPS);
+pub const KING_ATTACKS: [u64; 64] = step_table(&KING_STEPS);
+// squares a pawn of each color on the square attacks, indexed with color_index
+pub const PAWN_ATTACKS: [[u64; 64]; 2] = [step_table(&[(-1, 1), (1, 1)]), step_table(&[(-1, -1), (1, -1)])];
+
+// for two squares on the same rank, file or diagonal: the squares strictly between them in
+// BETWEEN and the whole line through both, edge to edge, in LINE. Both are empty otherwise
+const fn line_tables() -> ([[u64; 64]; 64], [[u64; 64]; 64]) {
+    let mut between = [[0; 64]; 64];
+    let mut line = [[0; 64]; 64];
+    let directions = [(1, 0), (0, 1), (1, 1), (1, -1), (-1, 0), (0, -1), (-1, -1), (-1, 1)];
+    let mut from = 0;
+    while from < 64 {
+        let mut d = 0;
+        while d < 8 {
+            let (df, dr) = directions[d];
+            // the full line: walk both ways from the square
+            let full = step_ray(from, df, dr) | step_ray(from, -df, -dr) | (1 << from);
+            let mut file = (from % 8) as i32 + df;
+            let mut rank = (from / 8) as i32 + dr;
+            let mut squares_between = 0;
+            while file >= 0 && file < 8 && rank >= 0 && rank < 8 {
+                let to = (rank * 8 + file) as usize;
+                between[from][to] = squares_between;
+                line[from][to] = full;
+                squares_between |= 1 << to;
+                file += df;
+                rank += dr;
+            }
+            d += 1;
+        }
+        from += 1;
+    }
+    (between, line)
+}
+
+const fn step_ray(square: usize, df: i32, dr: i32) -> u64 {
+    let mut ray = 0;
+    let mut file = (square % 8) as i32 + df;
+    let mut rank = (square / 8) as i32 + dr;
+    while file >= 0 && file < 8 && rank >= 0 && rank < 8 {
+        ray |= 1 << (rank * 8 + file);
+        file += df;
+        rank += dr;
+    }
+    ray
+}
+
+const LINE_TABLES: ([[u64; 64]; 64], [[u64; 64]; 64]) = line_tables();
+pub static BETWEEN: [[u64; 64]; 64] = LINE_TABLES.0;
+pub static LINE: [[u64; 64]; 64] = LINE_TABLES.1;
+
+// slow sliding attacks walking ray by ray, only used to fill the magic tables
+const fn sliding_attacks(square: usize, occupied: u64, directions: &[(i32, i32); 4]) -> u64 {
+    let mut attacks = 0;
+    let mut i = 0;
+    while i < 4 {
+        let (df, dr) = directions[i];
+        let mut file = (square % 8) as i32 + df;
+        let mut rank = (square / 8) as i32 + dr;
+        while file >= 0 && file < 8 && rank >= 0 && rank < 8 {
+            let bit = 1 << (rank * 8 + file);
+            attacks |= bit;
+            if occupied & bit != 0 {
+                break;
+            }
+            file += df;
+            rank += dr;
+        }
+        i += 1;
+    }
+    attacks
+}
+
+/* The squares whose occupancy matters for a slider, its rays without the last square of each
+one: a piece on the edge doesn't block anything behind it because there is nothing behind it.
+*/
+const fn relevant_mask(square: usize, directions: &[(i32, i32); 4]) -> u64 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < 4 {
+        let (df, dr) = directions[i];
+        let mut file = (square % 8) as i32 + df;
+        let mut rank = (square / 8) as i32 + dr;
+        while file + df >= 0 && file + df < 8 && rank + dr >= 0 && rank + dr < 8 {
+            mask |= 1 << (rank * 8 + file);
+            file += df;
+            rank += dr;
+        }
+        i += 1;
+    }
+    mask
+}
+
+/* Magic bitboards: for a slider on a square, the blockers that matter (occupied & mask) are
+multiplied by a magic number and the top bits of the product are used as an index in a table
+of precomputed attacks. The magic numbers were found by trial and error so that two blocker
+sets giving different attacks never land on the same index. "Fancy" because every square gets
+its own table of exactly 2^bits entries, all of them packed in one big array.
+*/
+struct Magic {
+    mask: u64,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+struct SliderTable {
+    magics: Vec<Magic>,
+    attacks: Vec<u64>,
+}
+
+impl SliderTable {
+    fn new(directions: &[(i32, i32); 4], magic_numbers: &[u64; 64]) -> Self {
+        let mut magics = Vec::with_capacity(64);
+        let mut attacks = Vec::new();
+
+        for (square, &magic) in magic_numbers.iter().enumerate() {
+            let mask = relevant_mask(square, directions);
+            let bits = mask.count_ones();
+            let offset = attacks.len();
+            attacks.resize(offset + (1 << bits), 0);
+
+            // walk every subset of the mask (carry-rippler trick)
+            let mut blockers: u64 = 0;
+            loop {
+                let index = offset + (blockers.wrapping_mul(magic) >> (64 - bits)) as usize;
+                let square_attacks = sliding_attacks(square, blockers, directions);
+                debug_assert!(attacks[index] == 0 || attacks[index] == square_attacks, "bad magic for square {}", square);
+                attacks[index] = square_attacks;
+                blockers = blockers.wrapping_sub(mask) & mask;
+                if blockers == 0 {
+                    break;
+                }
+            }
+
+            magics.push(Magic { mask, magic, shift: 64 - bits, offset });
+        }
+
+        SliderTable { magics, attacks }
+    }
+
+    fn attacks(&self, square: usize, occupied: u64) -> u64 {
+        let magic = &self.magics[square];
+        let index = ((occupied & magic.mask).wrapping_mul(magic.magic) >> magic.shift) as usize;
+        self.attacks[magic.offset + index]
+    }
+}
+
+static ROOK_TABLE: LazyLock<SliderTable> = LazyLock::new(|| SliderTable::new(&ROOK_DIRECTIONS, &ROOK_MAGICS));
+static BISHOP_TABLE: LazyLock<SliderTable> = LazyLock::new(|| SliderTable::new(&BISHOP_DIRECTIONS, &BISHOP_MAGICS));
+
+pub fn rook_attacks(square: usize, occupied: u64) -> u64 {
+    ROOK_TABLE.attacks(square, occupied)
+}
+
+pub fn bishop_attacks(square: usize, occupied: u64) -> u64 {
+    BISHOP_TABLE.attacks(square, occupied)
+}
+
+pub fn queen_attacks(square: usize, occupied: u64) -> u64 {
+    rook_attacks(square, occupied) | bishop_attacks(square, occupied)
+}
+
+impl Board {
+    // puts a piece on a square (or empties it) keeping the mailbox and the bitboards in sync
+    pub fn set_square(&mut self, square: usize, piece: Option<Piece>) {
+        if let Some(old) = &self.squares[square] {
+            self.bitboards[piece_index(old)] &= !square_bb(square);
+            self.occupancy[color_index(old.color())] &= !square_bb(square);
+        }
+        if let Some(new) = &piece {
+            self.bitboards[piece_index(new)] |= square_bb(square);
+            self.occupancy[color_index(new.color())] |= square_bb(square);
+        }
+        self.squares[square] = piece;
+    }
+
+    pub fn pieces(&self, piece: Piece) -> u64 {
+        self.bitboards[piece_index(&piece)]
+    }
+
+    pub fn occupied(&self) -> u64 {
+        self.occupancy[0] | self.occupancy[1]
+    }
+
+    pub fn color_occupancy(&self, color: Color) -> u64 {
+        self.occupancy[color_index(color)]
+    }
+
+    // every piece of the given color attacking the square
+    pub fn attackers(&self, square: usize, color: Color, occupied: u64) -> u64 {
+        let diagonal = self.pieces(Piece::Bishop(color)) | self.pieces(Piece::Queen(color));
+        let straight = self.pieces(Piece::Rook(color)) | self.pieces(Piece::Queen(color));
+        // a pawn attacks the square if a pawn of the other color on it would attack the pawn
+        (PAWN_ATTACKS[color_index(color.opposite())][square] & self.pieces(Piece::Pawn(color)))
+            | (KNIGHT_ATTACKS[square] & self.pieces(Piece::Knight(color)))
+            | (KING_ATTACKS[square] & self.pieces(Piece::King(color)))
+            | (bishop_attacks(square, occupied) & diagonal)
+            | (rook_attacks(square, occupied) & straight)
+    }
+
+    // rebuilds the bitboards from the mailbox
+    pub fn compute_bitboards(&self) -> ([u64; 12], [u64; 2]) {
+        let mut bitboards = [0; 12];
+        let mut occupancy = [0; 2];
+        for (square, piece) in self.squares.iter().enumerate() {
+            if let Some(piece) = piece {
+                bitboards[piece_index(piece)] |= square_bb(square);
+                occupancy[color_index(piece.color())] |= square_bb(square);
+            }
+        }
+        (bitboards, occupancy)
+    }
+}
+
+// found with a random search, see the comment on Magic
+const ROOK_MAGICS: [u64; 64] = [
+    0x7080008020104008, 0x2040004020001009, 0x0A00104200200880, 0x0280100081080184,
+    0x0200100805020020, 0xC600082402001001, 0x0880020000800100, 0xA080008004482500,
+    0x0088801020804000, 0x0001401000200240, 0x0001802000100180, 0x6410801000080080,
+    0x0002808008000400, 0x2103001208040100, 0x000400100104B208, 0x4482000108A04402,
+    0x0A0028800C400080, 0x0050014000402000, 0x0900110020010840, 0x0000220042001008,
+    0x0004008080080004, 0x0C04008004800200, 0x0010040001D00802, 0x00280A000050810C,
+    0x7100400080208000, 0x4001008100204000, 0x0000401100200500, 0x0000100100200901,
+    0x0900080100041100, 0x0432040080020080, 0x1001104400020188, 0x000C004600092184,
+    0x0200804000800024, 0xE010002002400040, 0xA010200080801000, 0x00D0001103002088,
+    0x0914000480800800, 0x0080020080800400, 0x4840011044000802, 0xC800208402000041,
+    0x0800208040008000, 0x0060002050004000, 0x2400200041010011, 0x1010000800108080,
+    0x0908040008008080, 0x3802000400808002, 0x0001000A00090004, 0x0848008908420014,
+    0x00C0002080005480, 0x0003043881400100, 0x0890002000108080, 0x0802801002080480,
+    0x0002002104100A00, 0x0063002400A82300, 0x0088111002580400, 0x8020040041008200,
+    0x0001218000C31101, 0x200100120020408A, 0x9020081040200301, 0x0022150028A01001,
+    0x4102002010080402, 0x0001000400020801, 0x8480020110981004, 0x0428010408204082,
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0xC084202400508104, 0x40100200810A1080, 0x000800CC00802000, 0x022440C182000220,
+    0x0B01104100000108, 0x4300900420400060, 0xC1008088A8400A00, 0x0002002118023000,
+    0x000110022808408C, 0x8000208200820080, 0x0401508102002040, 0x0802024081040002,
+    0x2200040420004011, 0x02880101601000C0, 0x0400048404210484, 0x6020005404013800,
+    0x0405004088020400, 0x0820409104410040, 0x2004008204001200, 0x0008000420401001,
+    0x1020820406A00400, 0x080A010100462A08, 0x20010400A8080221, 0x0206000040441480,
+    0x0022104040C40824, 0x12211000041C0860, 0x4408080004004010, 0x4520080021004088,
+    0x0080404004010040, 0x1308820242880400, 0x0004040418420201, 0x0101020C00208440,
+    0x0250500860700200, 0x0021080844425082, 0x3201040100088800, 0x0020400808108200,
+    0x0000544040140100, 0x0010820080041001, 0x002800A100408800, 0x040C008126888402,
+    0x004824102A140402, 0x0422081208000311, 0x000A210240488808, 0x4000002218004C00,
+    0x0000081904004040, 0x03A0060049406200, 0x020208010C078104, 0x1002060A42009111,
+    0x802200A404C0AC00, 0x0101110121200800, 0x0000420042080904, 0x1000015084040401,
+    0x1000081212021042, 0x00000AA008062000, 0x00A0210202005900, 0x010811291A220050,
+    0x7200C04808211040, 0x4400086605042100, 0xEC10002020841084, 0x1000180204840404,
+    0x0004411404050401, 0x0C018A0810840820, 0x1808080810008210, 0x4010013800838208,
+];
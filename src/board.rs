@@ -1,5 +1,6 @@
 use crate::movegen::Move;
 use crate::movegen;
+use crate::zobrist;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Board{
@@ -10,7 +11,12 @@ pub struct Board{
     pub halfmove_clock: u32, // para la regla de los 50 movimientos
     pub fullmove_number: u32, //para notación en general
     pub white_king: u16,
-    pub black_king: u16
+    pub black_king: u16,
+    pub hash: u64, // zobrist key of the position, see zobrist.rs
+    pub bitboards: [u64; 12], // one per piece, indexed like the zobrist keys, see bitboard.rs
+    pub occupancy: [u64; 2], // all the pieces of each color
+    pub castling_rooks: [u8; 4], // where the rook of each castling right starts, in CASTLING_RIGHTS order
+    pub chess960: bool, // Fischer Random, changes how castling moves and rights are written
 }
 
 
@@ -18,6 +24,26 @@ pub const WHITE_KINGSIDE_CASTLING_RIGHTS: u8 = 0b1000;
 pub const WHITE_QUEENSIDE_CASTLING_RIGHTS: u8 = 0b0100;
 pub const BLACK_KINGSIDE_CASTLING_RIGHTS: u8 = 0b0010;
 pub const BLACK_QUEENSIDE_CASTLING_RIGHTS: u8 = 0b0001;
+// white kingside, white queenside, black kingside, black queenside
+pub const CASTLING_RIGHTS: [u8; 4] = [WHITE_KINGSIDE_CASTLING_RIGHTS, WHITE_QUEENSIDE_CASTLING_RIGHTS,
+    BLACK_KINGSIDE_CASTLING_RIGHTS, BLACK_QUEENSIDE_CASTLING_RIGHTS];
+pub const STANDARD_CASTLING_ROOKS: [u8; 4] = [7, 0, 63, 56];
+
+/* The one square numbering everything uses: square 0 is a1, 7 is h1, 8 is a2 and 63 is h8, so
+square = rank * 8 + file with files and ranks counted from 0. FEN lists rank 8 first and the
+board is printed the same way, both go through these instead of counting on their own.
+*/
+pub const fn square_at(file: usize, rank: usize) -> usize {
+    rank * 8 + file
+}
+
+pub const fn file_of(square: usize) -> usize {
+    square % 8
+}
+
+pub const fn rank_of(square: usize) -> usize {
+    square / 8
+}
 
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -33,10 +59,13 @@ impl Color {
             Color::White => Color::Black
         }
     }
-    pub fn to_string(self) -> String {
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Color::Black => "Black".to_string(),
-            Color::White => "White".to_string()
+            Color::Black => write!(f, "Black"),
+            Color::White => write!(f, "White")
         }
     }
 }
@@ -72,7 +101,8 @@ pub struct UndoInfo {
     pub halfmove_clock: u32,
     pub special_info: SpecialInfo,
     pub white_king: u16,
-    pub black_king: u16
+    pub black_king: u16,
+    pub hash: u64,
 }
 
 
@@ -94,14 +124,23 @@ pub enum SpecialInfo{
 }
 
 
+impl Default for Board {
+    fn default() -> Self {
+        let mut board = Self::new();
+        board.setup_initial_position();
+        board
+
+    }
+}
+
 impl Board{
     pub fn new() -> Self {
         let squares:[Option<Piece>; 64] = [None; 64]; 
         let castling_rights = 0b1111;
         let en_passant_square = None;
         let halfmove_clock = 0;
-        let fullmove_number = 0;
-        let board = Board{
+        let fullmove_number = 1;
+        let mut board = Board{
             squares, 
             side_to_move: Color::White,
             castling_rights,
@@ -110,56 +149,116 @@ impl Board{
             fullmove_number,
             black_king: 60, // initial squares of black and white kings
             white_king: 4,
+            hash: 0,
+            bitboards: [0; 12],
+            occupancy: [0; 2],
+            castling_rooks: STANDARD_CASTLING_ROOKS,
+            chess960: false,
         };
+        board.hash = board.compute_hash();
         board
-        
-    }
-
-    pub fn default() -> Self {
-        let mut board = Self::new();
-        board.setup_initial_position();
-        board
-
     }
 
     pub fn setup_initial_position(&mut self){
         for i in 0..8 {
-            self.squares[8 + i] = Some(Piece::Pawn(Color::White));
-            self.squares[48 + i] = Some(Piece::Pawn(Color::Black));
+            self.set_square(8 + i, Some(Piece::Pawn(Color::White)));
+            self.set_square(48 + i, Some(Piece::Pawn(Color::Black)));
 
         }
 
-        self.squares[0] = Some(Piece::Rook(Color::White));
-        self.squares[7] = Some(Piece::Rook(Color::White));
-        self.squares[56] = Some(Piece::Rook(Color::Black));
-        self.squares[63] = Some(Piece::Rook(Color::Black));
+        self.set_square(0, Some(Piece::Rook(Color::White)));
+        self.set_square(7, Some(Piece::Rook(Color::White)));
+        self.set_square(56, Some(Piece::Rook(Color::Black)));
+        self.set_square(63, Some(Piece::Rook(Color::Black)));
 
-        self.squares[1] = Some(Piece::Knight(Color::White));
-        self.squares[6] = Some(Piece::Knight(Color::White));
-        self.squares[57] = Some(Piece::Knight(Color::Black));
-        self.squares[62] = Some(Piece::Knight(Color::Black));
+        self.set_square(1, Some(Piece::Knight(Color::White)));
+        self.set_square(6, Some(Piece::Knight(Color::White)));
+        self.set_square(57, Some(Piece::Knight(Color::Black)));
+        self.set_square(62, Some(Piece::Knight(Color::Black)));
         
-        self.squares[2] = Some(Piece::Bishop(Color::White));
-        self.squares[5] = Some(Piece::Bishop(Color::White));
-        self.squares[58] = Some(Piece::Bishop(Color::Black));
-        self.squares[61] = Some(Piece::Bishop(Color::Black));
+        self.set_square(2, Some(Piece::Bishop(Color::White)));
+        self.set_square(5, Some(Piece::Bishop(Color::White)));
+        self.set_square(58, Some(Piece::Bishop(Color::Black)));
+        self.set_square(61, Some(Piece::Bishop(Color::Black)));
+
+        self.set_square(3, Some(Piece::Queen(Color::White)));
+        self.set_square(59, Some(Piece::Queen(Color::Black)));
 
-        self.squares[3] = Some(Piece::Queen(Color::White));
-        self.squares[59] = Some(Piece::Queen(Color::Black));
+        self.set_square(4, Some(Piece::King(Color::White)));
+        self.set_square(60, Some(Piece::King(Color::Black)));
 
-        self.squares[4] = Some(Piece::King(Color::White));
-        self.squares[60] = Some(Piece::King(Color::Black));
+        self.hash = self.compute_hash();
 
     }
 
+    /* The Chess960 starting positions numbered 0 to 959 the standard way (Scharnagl), 518 is the
+    normal setup. The number picks the light square bishop, the dark square bishop, the queen and
+    the two knights in that order, the three squares left get rook, king, rook so the king is
+    always between its rooks. Black mirrors White.
+    */
+    pub fn from_chess960_index(index: u16) -> Result<Self, String> {
+        if index >= 960 {
+            return Err(format!("Chess960 position {} out of range 0-959", index));
+        }
+        const KNIGHTS: [(usize, usize); 10] = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)];
+
+        // white's pieces as FEN letters, black gets the same in lowercase
+        let mut back_rank = [' '; 8];
+        let mut n = index as usize;
+        back_rank[2 * (n % 4) + 1] = 'B';
+        n /= 4;
+        back_rank[2 * (n % 4)] = 'B';
+        n /= 4;
+
+        // the n-th empty file, counting only the ones still free
+        let empty_file = |back_rank: &[char; 8], n: usize| {
+            (0..8).filter(|&file| back_rank[file] == ' ').nth(n).unwrap()
+        };
+        let queen = empty_file(&back_rank, n % 6);
+        back_rank[queen] = 'Q';
+        n /= 6;
+
+        let (first, second) = KNIGHTS[n];
+        let first = empty_file(&back_rank, first);
+        let second = empty_file(&back_rank, second);
+        back_rank[first] = 'N';
+        back_rank[second] = 'N';
+
+        for piece in ['R', 'K', 'R'] {
+            let file = empty_file(&back_rank, 0);
+            back_rank[file] = piece;
+        }
+
+        let mut board = Board::new();
+        let mut rooks = Vec::new();
+        for (file, &piece) in back_rank.iter().enumerate() {
+            board.set_square(file, Some(Self::char_to_piece(piece)?));
+            board.set_square(56 + file, Some(Self::char_to_piece(piece.to_ascii_lowercase())?));
+            board.set_square(8 + file, Some(Piece::Pawn(Color::White)));
+            board.set_square(48 + file, Some(Piece::Pawn(Color::Black)));
+            match piece {
+                'K' => {
+                    board.white_king = file as u16;
+                    board.black_king = 56 + file as u16;
+                },
+                'R' => rooks.push(file as u8),
+                _ => {}
+            }
+        }
+
+        board.castling_rooks = [rooks[1], rooks[0], 56 + rooks[1], 56 + rooks[0]];
+        board.chess960 = true;
+        board.hash = board.compute_hash();
+        Ok(board)
+    }
+
     pub fn print_board(&self){
         println!();
         println!("  +-----------------+");
         for rank in (0..8).rev(){
             print!("{} | ", rank + 1); 
             for file in 0..8 {
-                let index = rank * 8 + file;
-                let square = &self.squares[index];
+                let square = &self.squares[square_at(file, rank)];
 
                 match square {
                     Some(piece) => print!("{} ", Self::piece_to_char(piece)),
@@ -185,6 +284,9 @@ impl Board{
     }
 
     pub fn make_move(&mut self, m: Move) -> UndoInfo {
+        debug_assert_eq!(self.hash, self.compute_hash(), "zobrist key out of sync before {:?}", m);
+        debug_assert_eq!((self.bitboards, self.occupancy), self.compute_bitboards(), "bitboards out of sync before {:?}", m);
+
         let mut undo_info = UndoInfo {
             captured_piece: None,
             en_passant_square: self.en_passant_square,
@@ -192,8 +294,8 @@ impl Board{
             halfmove_clock: self.halfmove_clock,
             special_info: SpecialInfo::None,
             white_king: self.white_king,
-            black_king: self.black_king
-
+            black_king: self.black_king,
+            hash: self.hash,
         };
 
         let from = m.get_from() as usize;
@@ -201,77 +303,79 @@ impl Board{
         
         let moving_piece = self.squares[from];
 
+        // the old castling rights and en passant square go out of the key, the new ones come in at the end
+        self.hash ^= zobrist::KEYS.castling[self.castling_rights as usize];
+        self.hash ^= zobrist::en_passant_key(self.en_passant_square);
+        if let Some(piece) = &moving_piece {
+            self.hash ^= zobrist::piece_key(piece, from);
+        }
+
         self.en_passant_square = None;
         
         if let Some(Piece::King(color)) = self.squares[from]{
+            // castling is written as the king taking its own rook, that's not where it ends up
+            let king_to = if m.is_castle() { m.castling_destinations().0 as u16 } else { m.get_to() };
             match color {
-                Color::White => self.white_king = m.get_to(),
-                Color::Black => self.black_king = m.get_to()
+                Color::White => self.white_king = king_to,
+                Color::Black => self.black_king = king_to
             }
+            self.castling_rights &= match color {
+                Color::White => !(WHITE_KINGSIDE_CASTLING_RIGHTS | WHITE_QUEENSIDE_CASTLING_RIGHTS),
+                Color::Black => !(BLACK_KINGSIDE_CASTLING_RIGHTS | BLACK_QUEENSIDE_CASTLING_RIGHTS),
+            };
         }
 
-        if let Some(piece) = self.squares[from] {
-            match piece {
-                Piece::King(_) => self.update_castling_rights(from, 0),
-                Piece::Rook(_) => self.update_castling_rights(0, from),
-                _ => {}
-            }
-        }
+        // moving a rook or capturing it on its starting square loses its castling right
+        self.update_castling_rights(from);
+        self.update_castling_rights(to);
         
         if m.is_capture() {
-            if let Some(Piece::Rook(_)) = self.squares[to] {
-                self.update_castling_rights(0, to);
-            }
             if m.is_en_passant() {
                 let captured_pawn_square = match self.side_to_move {
                     Color::White => to - 8,
                     Color::Black => to + 8,
                 };
                 undo_info.captured_piece = self.squares[captured_pawn_square];
+                if let Some(pawn) = &undo_info.captured_piece {
+                    self.hash ^= zobrist::piece_key(pawn, captured_pawn_square);
+                }
                 undo_info.special_info = SpecialInfo::EnPassant { en_passant_square: captured_pawn_square as u16 };
-                self.squares[captured_pawn_square] = None;
-                self.squares[to] = self.squares[from];
-                self.squares[from] = None;
+                self.set_square(captured_pawn_square, None);
+                self.set_square(to, self.squares[from]);
+                self.set_square(from, None);
             } else {
                 undo_info.captured_piece = self.squares[to];
+                if let Some(piece) = &undo_info.captured_piece {
+                    self.hash ^= zobrist::piece_key(piece, to);
+                }
 
-                self.squares[to] = self.squares[from];
-                self.squares[from] = None;
+                self.set_square(to, self.squares[from]);
+                self.set_square(from, None);
 
             }
             self.halfmove_clock = 0;
 
         } else if m.is_castle() {
-            let (rook_from, rook_to):(u16, u16); 
-
-            if m.is_castle_kingside() { 
-                (rook_from, rook_to) = match self.side_to_move {
-                    Color::White => (7, 5),
-                    Color::Black => (63, 61),
-                };
-
-            } else {
-                (rook_from, rook_to) = match self.side_to_move {
-                    Color::White => (0, 3),
-                    Color::Black => (56, 59),
-                };
-                 
-            }
-
-            undo_info.special_info = SpecialInfo::Castle { rook_from: rook_from, rook_to: rook_to };
-
-            self.update_castling_rights(from, rook_from as usize);
-
-            self.squares[from] = None;
-            self.squares[to] = Some(Piece::King(self.side_to_move));
-            self.squares[rook_from as usize] = None;
-            self.squares[rook_to as usize] = Some(Piece::Rook(self.side_to_move));
-            self.halfmove_clock = 0;
+            // the move goes from the king to its rook, see Move::castling_destinations
+            let rook_from = to;
+            let (king_to, rook_to) = m.castling_destinations();
+
+            undo_info.special_info = SpecialInfo::Castle { rook_from: rook_from as u16, rook_to: rook_to as u16 };
+
+            // in Chess960 the king or the rook can end up where the other one started,
+            // so both leave the board before coming back
+            self.set_square(from, None);
+            self.set_square(rook_from, None);
+            self.set_square(king_to, Some(Piece::King(self.side_to_move)));
+            self.set_square(rook_to, Some(Piece::Rook(self.side_to_move)));
+            self.hash ^= zobrist::piece_key(&Piece::Rook(self.side_to_move), rook_from);
+            self.hash ^= zobrist::piece_key(&Piece::Rook(self.side_to_move), rook_to);
+            self.hash ^= zobrist::piece_key(&Piece::King(self.side_to_move), king_to);
 
         } else {
             
-            self.squares[to] = self.squares[from];
-            self.squares[from] = None;
+            self.set_square(to, self.squares[from]);
+            self.set_square(from, None);
             
         }
 
@@ -287,8 +391,8 @@ impl Board{
             };
             undo_info.special_info = SpecialInfo::Promotion;
 
-            self.squares[from] = None;
-            self.squares[to] = Some(promotion_piece);
+            self.set_square(from, None);
+            self.set_square(to, Some(promotion_piece));
             self.halfmove_clock = 0;
 
         } else if moving_piece == Some(Piece::Pawn(self.side_to_move)) {
@@ -300,14 +404,31 @@ impl Board{
                 self.en_passant_square = Some(ep_square as u8);
             }
             self.halfmove_clock = 0;
-        } else {
+        } else if !m.is_capture() {
             self.halfmove_clock += 1;
         }
 
+        // whatever ended up on the destination square, the moved piece or the promoted one
+        if !m.is_castle() && let Some(piece) = &self.squares[to] {
+            self.hash ^= zobrist::piece_key(piece, to);
+        }
+        self.hash ^= zobrist::KEYS.castling[self.castling_rights as usize];
+        self.hash ^= zobrist::en_passant_key(self.en_passant_square);
+        self.hash ^= zobrist::KEYS.side_to_move;
+
+        if self.side_to_move == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = self.side_to_move.opposite();
+
         undo_info
     }
 
     pub fn unmake_move(&mut self, m: Move, undo: UndoInfo) {
+        self.side_to_move = self.side_to_move.opposite();
+        if self.side_to_move == Color::Black {
+            self.fullmove_number -= 1;
+        }
         let from = m.get_from() as usize;
         let to = m.get_to() as usize;
         self.castling_rights = undo.castling_rights;
@@ -315,53 +436,40 @@ impl Board{
         self.halfmove_clock = undo.halfmove_clock;
         self.white_king = undo.white_king;
         self.black_king = undo.black_king;
+        self.hash = undo.hash;
 
         match undo.special_info {
             SpecialInfo::EnPassant { en_passant_square } => {
-                self.squares[from] = self.squares[to];
-                self.squares[to] = None;
-                self.squares[en_passant_square as usize] = undo.captured_piece;
+                self.set_square(from, self.squares[to]);
+                self.set_square(to, None);
+                self.set_square(en_passant_square as usize, undo.captured_piece);
             },
             SpecialInfo::Castle { rook_from, rook_to } => {
-                self.squares[from] = self.squares[to];
-                self.squares[to] = None;
-                self.squares[rook_from as usize] = self.squares[rook_to as usize];
-                self.squares[rook_to as usize] = None;
-            }, 
+                let (king_to, _) = m.castling_destinations();
+                self.set_square(king_to, None);
+                self.set_square(rook_to as usize, None);
+                self.set_square(from, Some(Piece::King(self.side_to_move)));
+                self.set_square(rook_from as usize, Some(Piece::Rook(self.side_to_move)));
+            },
             SpecialInfo::Promotion => {
-                self.squares[from] = Some(Piece::Pawn(self.side_to_move));
-                self.squares[to] = undo.captured_piece;
+                self.set_square(from, Some(Piece::Pawn(self.side_to_move)));
+                self.set_square(to, undo.captured_piece);
             },
             SpecialInfo::None => {
-                self.squares[from] = self.squares[to];
-                self.squares[to] = undo.captured_piece;
+                self.set_square(from, self.squares[to]);
+                self.set_square(to, undo.captured_piece);
             }
         }
 
     }
 
-    pub fn update_castling_rights(&mut self, from:usize, rook_from:usize) {
-        if self.castling_rights == 0 {
-            return;
-        }
-
-        if let Some(Piece::King(color)) = self.squares[from] {
-            match color {
-                Color::Black => self.castling_rights &= !BLACK_QUEENSIDE_CASTLING_RIGHTS
-                & !BLACK_KINGSIDE_CASTLING_RIGHTS, 
-                Color::White => self.castling_rights &= !WHITE_QUEENSIDE_CASTLING_RIGHTS
-                & !WHITE_KINGSIDE_CASTLING_RIGHTS,
-            };
-        }
-
-        match rook_from {
-            0 => self.castling_rights &= !WHITE_QUEENSIDE_CASTLING_RIGHTS,
-            7 => self.castling_rights &= !WHITE_KINGSIDE_CASTLING_RIGHTS,
-            56 => self.castling_rights &= !BLACK_QUEENSIDE_CASTLING_RIGHTS,
-            63 => self.castling_rights &= !BLACK_KINGSIDE_CASTLING_RIGHTS,
-            _ => {}
+    // clears the castling right of the rook starting on this square, if there is one
+    pub fn update_castling_rights(&mut self, square: usize) {
+        for (right, &rook_square) in CASTLING_RIGHTS.iter().zip(self.castling_rooks.iter()) {
+            if rook_square as usize == square {
+                self.castling_rights &= !right;
+            }
         }
-
     }
 
     pub fn piece_to_char(piece: &Piece) -> char {
@@ -398,25 +506,29 @@ impl Board{
     }
 
     pub fn is_check(&self) -> bool {
-        let king_pos = match self.side_to_move {
-            Color::White => self.white_king,
-            Color::Black => self.black_king,
-        };
-        movegen::is_square_attacked(&self, king_pos)
+        movegen::is_square_attacked(self, self.king_square(self.side_to_move) as u16)
     }
 
+    pub fn king_square(&self, color: Color) -> usize {
+        match color {
+            Color::White => self.white_king as usize,
+            Color::Black => self.black_king as usize,
+        }
+    }
+
+    // "e4" to its square number, see square_at
     pub fn str_to_square(coords: &str) -> Result<usize, String> {
-        let file_char = coords.chars().nth(0).unwrap();
-        let rank_char = coords.chars().nth(1).unwrap();
+        let mut chars = coords.chars();
+        let (Some(file_char), Some(rank_char), None) = (chars.next(), chars.next(), chars.next()) else {
+            return Err(format!("Invalid square: {}", coords));
+        };
         if !('a'..='h').contains(&file_char) || !('1'..='8').contains(&rank_char) {
             return Err(format!("Invalid square: {}", coords));
         }
 
-        let file_from = (file_char as u8 - b'a') as usize;
-        let rank_from = (rank_char as u8 - b'1') as usize;
-
-        let square = rank_from * 8 + file_from;
-        return Ok(square);
+        let file = (file_char as u8 - b'a') as usize;
+        let rank = (rank_char as u8 - b'1') as usize;
+        Ok(square_at(file, rank))
     }
 
 }
@@ -87,27 +87,25 @@ pub fn eval(board: &Board) -> i32 {
 pub fn count_material(board: &Board, color: Color) -> i32 {
     let mut material = 0;
     for (index, piece) in board.squares.iter().enumerate() {
-        if let Some(piece) = piece {
-            if piece.color() == color {
-                material += piece_value(&piece);
-
-                let piece_type_index = match piece {
-                    Piece::Pawn(_) => 0, 
-                    Piece::Knight(_) => 1, 
-                    Piece::Bishop(_) => 2, 
-                    Piece::Rook(_) => 3, 
-                    Piece::Queen(_) => 4, 
-                    Piece::King(_) => 5, 
-                };
-
-                let table_index = if piece.color() == Color::White {
-                    63 - index
-                } else {
-                    index
-                };
-
-                material += PIECE_SQUARE_TABLE[piece_type_index][table_index];
-            }
+        if let Some(piece) = piece && piece.color() == color {
+            material += piece_value(piece);
+
+            let piece_type_index = match piece {
+                Piece::Pawn(_) => 0, 
+                Piece::Knight(_) => 1, 
+                Piece::Bishop(_) => 2, 
+                Piece::Rook(_) => 3, 
+                Piece::Queen(_) => 4, 
+                Piece::King(_) => 5, 
+            };
+
+            let table_index = if piece.color() == Color::White {
+                63 - index
+            } else {
+                index
+            };
+
+            material += PIECE_SQUARE_TABLE[piece_type_index][table_index];
         }
     }
     material
@@ -1,12 +1,15 @@
-use crate::board::Board;
+use crate::bitboard::color_index;
+use crate::board::{file_of, rank_of, square_at, Board};
 use crate::board::Color;
 use crate::board::Piece;
-use crate::board::BLACK_KINGSIDE_CASTLING_RIGHTS;
-use crate::board::BLACK_QUEENSIDE_CASTLING_RIGHTS;
-use crate::board::WHITE_KINGSIDE_CASTLING_RIGHTS;
-use crate::board::WHITE_QUEENSIDE_CASTLING_RIGHTS;
+use crate::board::CASTLING_RIGHTS;
+use crate::board::STANDARD_CASTLING_ROOKS;
+use crate::movegen::Move;
+use crate::validate::PositionError;
 
 
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
 /* Fen is a notation method for boardstates. it is divided by spaces first in 6 parts, 
 1- piece position (divided by /, a number indicates number of squares empty, a letter a piece)
 2- active color
@@ -15,109 +18,339 @@ use crate::board::WHITE_QUEENSIDE_CASTLING_RIGHTS;
 5- halfmove clock
 6- fullmove number
 as an example     let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"; is the starting position
+
+For Chess960 the castling field says which rook each right belongs to. X-FEN keeps KQkq for the
+outermost rook on that side of the king and uses the rook's file letter (A-H white, a-h black)
+only when there's another rook further out, Shredder-FEN always uses the file letters.
+Both are accepted, to_fen writes X-FEN (plain KQkq in normal chess) and to_shredder_fen the other.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FenField {
+    Pieces,
+    SideToMove,
+    Castling,
+    EnPassant,
+    HalfmoveClock,
+    FullmoveNumber,
+    Operations, // EPD only, everything after the castling and en passant fields
+}
+
+/* What's wrong with a FEN string. Every error knows the field it was found in and its character
+offset in the whole string, so tools importing lots of positions can match on the kind of
+problem and point at it. Problems with the position as a whole (the kings, the side not to move
+in check) point at the start of the field that makes it wrong.
 */
+#[derive(Clone, Debug, PartialEq)]
+pub enum FenError {
+    FieldCount { found: usize },
+    BadRank { rank: usize, offset: usize }, // wrong number of ranks or of squares in a rank
+    BadPiece { ch: char, offset: usize },
+    BadSideToMove { offset: usize },
+    BadCastling { ch: char, offset: usize },
+    BadEnPassant { offset: usize },
+    BadHalfmoveClock { offset: usize },
+    BadFullmoveNumber { offset: usize },
+    MissingKing { color: Color },
+    ExtraKing { color: Color, offset: usize },
+    PawnOnBackRank { offset: usize },
+    OpponentInCheck { offset: usize },
+    IllegalPosition { problems: Vec<PositionError> }, // only from the strict mode, see validate.rs
+    BadOpcode { offset: usize }, // EPD operations
+    BadOperand { opcode: String, offset: usize },
+}
+
+impl FenError {
+    pub fn field(&self) -> FenField {
+        match self {
+            FenError::FieldCount { .. } | FenError::BadRank { .. } | FenError::BadPiece { .. } |
+            FenError::MissingKing { .. } | FenError::ExtraKing { .. } | FenError::PawnOnBackRank { .. } |
+            FenError::IllegalPosition { .. } => FenField::Pieces,
+            FenError::BadSideToMove { .. } | FenError::OpponentInCheck { .. } => FenField::SideToMove,
+            FenError::BadCastling { .. } => FenField::Castling,
+            FenError::BadEnPassant { .. } => FenField::EnPassant,
+            FenError::BadHalfmoveClock { .. } => FenField::HalfmoveClock,
+            FenError::BadFullmoveNumber { .. } => FenField::FullmoveNumber,
+            FenError::BadOpcode { .. } | FenError::BadOperand { .. } => FenField::Operations,
+        }
+    }
+
+    // character offset in the FEN string
+    pub fn offset(&self) -> usize {
+        match self {
+            FenError::FieldCount { .. } | FenError::MissingKing { .. } | FenError::IllegalPosition { .. } => 0,
+            FenError::BadRank { offset, .. } | FenError::BadPiece { offset, .. } |
+            FenError::BadSideToMove { offset } | FenError::BadCastling { offset, .. } |
+            FenError::BadEnPassant { offset } | FenError::BadHalfmoveClock { offset } |
+            FenError::BadFullmoveNumber { offset } | FenError::ExtraKing { offset, .. } |
+            FenError::PawnOnBackRank { offset } | FenError::OpponentInCheck { offset } |
+            FenError::BadOpcode { offset } | FenError::BadOperand { offset, .. } => *offset,
+        }
+    }
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::FieldCount { found } => write!(f, "FEN must have 6 fields, found {}", found),
+            FenError::BadRank { rank, offset } => write!(f, "rank {} doesn't have 8 squares (at {})", rank, offset),
+            FenError::BadPiece { ch, offset } => write!(f, "invalid piece '{}' at {}", ch, offset),
+            FenError::BadSideToMove { offset } => write!(f, "side to move must be w or b (at {})", offset),
+            FenError::BadCastling { ch, offset } => write!(f, "invalid castling right '{}' at {}", ch, offset),
+            FenError::BadEnPassant { offset } => write!(f, "invalid en passant square at {}", offset),
+            FenError::BadHalfmoveClock { offset } => write!(f, "invalid halfmove clock at {}", offset),
+            FenError::BadFullmoveNumber { offset } => write!(f, "invalid fullmove number at {}", offset),
+            FenError::MissingKing { color } => write!(f, "{} has no king", color),
+            FenError::ExtraKing { color, offset } => write!(f, "{} has more than one king (at {})", color, offset),
+            FenError::PawnOnBackRank { offset } => write!(f, "pawn on the first or last rank at {}", offset),
+            FenError::OpponentInCheck { offset } => write!(f, "the side not to move is in check (at {})", offset),
+            FenError::IllegalPosition { problems } => {
+                let problems: Vec<String> = problems.iter().map(|problem| problem.to_string()).collect();
+                write!(f, "illegal position: {}", problems.join(", "))
+            },
+            FenError::BadOpcode { offset } => write!(f, "invalid EPD operation at {}", offset),
+            FenError::BadOperand { opcode, offset } => write!(f, "invalid operand for {} at {}", opcode, offset),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+// most of the code passes errors around as strings
+impl From<FenError> for String {
+    fn from(error: FenError) -> Self {
+        error.to_string()
+    }
+}
+
 impl Board {
-    pub fn from_fen(fen: &str) -> Result<Self, String> {
-        let parts: Vec<&str> = fen.split_whitespace().collect();
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        // every field with the offset where it starts
+        let mut parts: Vec<(usize, &str)> = Vec::new();
+        let mut start = None;
+        for (offset, ch) in fen.char_indices().chain(std::iter::once((fen.len(), ' '))) {
+            match (ch.is_whitespace(), start) {
+                (true, Some(field_start)) => {
+                    parts.push((field_start, &fen[field_start..offset]));
+                    start = None;
+                },
+                (false, None) => start = Some(offset),
+                _ => {}
+            }
+        }
         if parts.len() != 6 {
-            return Err("FEN must have 6 parts".to_string());
+            return Err(FenError::FieldCount { found: parts.len() });
         }
         let mut board = Board::new();
 
-        board.parse_pieces(parts[0])?;
+        board.parse_pieces(parts[0].1)?;
 
-        board.side_to_move = match parts[1] {
+        board.side_to_move = match parts[1].1 {
             "w" => Color::White,
             "b" => Color::Black,
-            _ => return Err("Invalid color".to_string()),
+            _ => return Err(FenError::BadSideToMove { offset: parts[1].0 }),
         };
 
-        board.castling_rights = Self::parse_castling_rights(parts[2])?;
+        board.parse_castling_rights(parts[2].1, parts[2].0)?;
+
+        board.en_passant_square = board.parse_en_passant(parts[3].1, parts[3].0)?;
 
-        board.en_passant_square = Self::parse_en_passant(parts[3])?;
+        board.halfmove_clock = parts[4].1.parse().map_err(|_| FenError::BadHalfmoveClock { offset: parts[4].0 })?;
+        board.fullmove_number = parts[5].1.parse().map_err(|_| FenError::BadFullmoveNumber { offset: parts[5].0 })?;
 
-        board.halfmove_clock = parts[4].parse().map_err(|_| "Invalid halfmove clock".to_string())?;
-        board.fullmove_number = parts[5].parse().map_err(|_| "Invalid fullmove number".to_string())?;
+        // the side that just moved can't have left its king attacked
+        if board.attackers(board.king_square(board.side_to_move.opposite()), board.side_to_move, board.occupied()) != 0 {
+            return Err(FenError::OpponentInCheck { offset: parts[1].0 });
+        }
+
+        board.hash = board.compute_hash();
 
 
         Ok(board)
     }
     
-    pub fn parse_pieces(&mut self, pieces: &str) -> Result<(), String> {
+    // the first FEN field, offsets count from its start
+    pub fn parse_pieces(&mut self, pieces: &str) -> Result<(), FenError> {
         let ranks: Vec<&str> = pieces.split('/').collect();
         if ranks.len() != 8 {
-            return Err("Not 8 ranks".to_string());
+            // the first rank missing, or 0 when there are too many
+            return Err(FenError::BadRank { rank: 8usize.saturating_sub(ranks.len()), offset: pieces.len() });
         }
+        let mut kings = [0; 2];
+        let mut offset = 0;
+        // FEN starts from rank 8, see board::square_at
         for (rank_index, pieces) in ranks.iter().enumerate() {
+            let rank_index = 7 - rank_index;
             let mut file_index = 0;
             for ch in pieces.chars() {
                 if file_index >= 8 {
-                    return Err("too many squares in a rank".to_string());
+                    return Err(FenError::BadRank { rank: rank_index + 1, offset });
                 }
                 if ch.is_ascii_digit() {
                     let empty = ch.to_digit(10).unwrap() as usize;
-                    if empty == 0 || empty > 8 {
-                        return Err("invalid number of empty squares".to_string());
+                    if empty == 0 || empty > 8 - file_index {
+                        return Err(FenError::BadRank { rank: rank_index + 1, offset });
                     }
                     file_index += empty;
                 } else {
-                    let piece = Self::char_to_piece(ch)?;
-                    let square = rank_index * 8 + file_index;
-                    self.squares[square] = Some(piece);
+                    let piece = Self::char_to_piece(ch).map_err(|_| FenError::BadPiece { ch, offset })?;
+                    let square = square_at(file_index, rank_index);
+                    if matches!(piece, Piece::Pawn(_)) && (rank_index == 0 || rank_index == 7) {
+                        return Err(FenError::PawnOnBackRank { offset });
+                    }
+                    self.set_square(square, Some(piece));
                     
-                    match piece {
-                        Piece::King(Color::White) => self.white_king = square as u16,
-                        Piece::King(Color::Black) => self.black_king= square as u16,
-                        _ => {}
+                    if let Piece::King(color) = piece {
+                        kings[color_index(color)] += 1;
+                        if kings[color_index(color)] > 1 {
+                            return Err(FenError::ExtraKing { color, offset });
+                        }
+                        match color {
+                            Color::White => self.white_king = square as u16,
+                            Color::Black => self.black_king = square as u16,
+                        }
                     }
                     file_index += 1;
                 }
+                offset += ch.len_utf8();
             }
             if file_index != 8 {
-                return Err("Rank doesnt have 8 squares exactly".to_string());
+                return Err(FenError::BadRank { rank: rank_index + 1, offset });
+            }
+            offset += 1; // the '/'
+        }
+        for color in [Color::White, Color::Black] {
+            if kings[color_index(color)] == 0 {
+                return Err(FenError::MissingKing { color });
             }
         }
         Ok(())
     }
  
-    fn parse_castling_rights(rights: &str) -> Result<u8, String> {
+    // needs the pieces already on the board, the rights are found from where the rooks are
+    fn parse_castling_rights(&mut self, rights: &str, field_offset: usize) -> Result<(), FenError> {
+        self.castling_rights = 0;
+        self.castling_rooks = STANDARD_CASTLING_ROOKS;
         if rights == "-" {
-            return Ok(0);
+            return Ok(());
+        }
+
+        for (offset, ch) in rights.char_indices() {
+            let error = FenError::BadCastling { ch, offset: field_offset + offset };
+            let color = if ch.is_ascii_uppercase() { Color::White } else { Color::Black };
+            let back_rank = match color {
+                Color::White => 0,
+                Color::Black => 56,
+            };
+            let king_file = self.king_square(color) % 8;
+            if self.king_square(color) / 8 * 8 != back_rank {
+                return Err(error);
+            }
+            let is_rook = |file: usize| self.squares[back_rank + file] == Some(Piece::Rook(color));
+
+            // KQkq is the outermost rook on that side, a file letter names the rook
+            let rook_file = match ch.to_ascii_lowercase() {
+                'k' => (king_file + 1..8).rev().find(|&file| is_rook(file)),
+                'q' => (0..king_file).find(|&file| is_rook(file)),
+                'a'..='h' => Some((ch.to_ascii_lowercase() as u8 - b'a') as usize).filter(|&file| is_rook(file)),
+                _ => None,
+            };
+            let Some(rook_file) = rook_file else {
+                return Err(error);
+            };
+            if rook_file == king_file {
+                return Err(error);
+            }
+            let kingside = rook_file > king_file;
+            let index = match (color, kingside) {
+                (Color::White, true) => 0,
+                (Color::White, false) => 1,
+                (Color::Black, true) => 2,
+                (Color::Black, false) => 3,
+            };
+            self.castling_rights |= CASTLING_RIGHTS[index];
+            self.castling_rooks[index] = (back_rank + rook_file) as u8;
         }
-        let mut castling_rights = 0u8;
 
-        for ch in rights.chars() {
-            match ch {
-                'K' => castling_rights |= WHITE_KINGSIDE_CASTLING_RIGHTS,
-                'Q' => castling_rights |= WHITE_QUEENSIDE_CASTLING_RIGHTS,
-                'k' => castling_rights |= BLACK_KINGSIDE_CASTLING_RIGHTS,
-                'q' => castling_rights |= BLACK_QUEENSIDE_CASTLING_RIGHTS,
-                _ => return Err(format!("invalid castling right {}", ch)),
+        // kings and rooks where normal chess can't have them with those rights
+        self.chess960 = (0..4).any(|index| {
+            self.castling_rights & CASTLING_RIGHTS[index] != 0 &&
+            (self.castling_rooks[index] != STANDARD_CASTLING_ROOKS[index] ||
+             self.king_square(if index < 2 { Color::White } else { Color::Black }) % 8 != 4)
+        });
+        Ok(())
+    }
+
+    // the castling field, with file letters for every right (Shredder) or only where KQkq is ambiguous (X-FEN)
+    fn castling_to_string(&self, shredder: bool) -> String {
+        let mut castling = String::new();
+        for (index, &right) in CASTLING_RIGHTS.iter().enumerate() {
+            if self.castling_rights & right == 0 {
+                continue;
             }
+            let color = if index < 2 { Color::White } else { Color::Black };
+            let rook = self.castling_rooks[index] as usize;
+            let king = self.king_square(color);
+            let kingside = index % 2 == 0;
+
+            // another rook further out on the same side would be the one KQkq means
+            let outermost = if kingside { rook + 1..king / 8 * 8 + 8 } else { king / 8 * 8..rook };
+            let ambiguous = outermost.into_iter().any(|square| self.squares[square] == Some(Piece::Rook(color)));
+
+            let letter = if shredder || ambiguous {
+                (b'a' + (rook % 8) as u8) as char
+            } else if kingside {
+                'k'
+            } else {
+                'q'
+            };
+            castling.push(match color {
+                Color::White => letter.to_ascii_uppercase(),
+                Color::Black => letter,
+            });
+        }
+
+        if castling.is_empty() {
+            castling.push('-');
         }
-        Ok(castling_rights)
+        castling
     }
 
-    fn parse_en_passant(coordinates: &str) -> Result<Option<u8>, String> {
+    // the square has to be right behind a pawn that just moved two squares, rank 6 when white is to move
+    fn parse_en_passant(&self, coordinates: &str, offset: usize) -> Result<Option<u8>, FenError> {
         if coordinates == "-" {
             return Ok(None);
         }
 
-        let square = Self::string_to_square(coordinates)?;
-        return Ok(Some(square))
+        let square = Self::string_to_square(coordinates).map_err(|_| FenError::BadEnPassant { offset })?;
+        let rank = match self.side_to_move {
+            Color::White => 5,
+            Color::Black => 2,
+        };
+        if square / 8 != rank {
+            return Err(FenError::BadEnPassant { offset });
+        }
+        Ok(Some(square))
     }
 
     pub fn to_fen(&self) -> String {
+        self.fen_with_castling(self.castling_to_string(false))
+    }
+
+    pub fn to_shredder_fen(&self) -> String {
+        self.fen_with_castling(self.castling_to_string(true))
+    }
+
+    fn fen_with_castling(&self, castling: String) -> String {
         let mut fen = String::new();
 
-        for rank in 0..8 {
+        for rank in (0..8).rev() {
             let mut empty_spaces = 0;
 
             for file in 0..8 {
-                let square = rank * 8 + file;
-                if let Some(piece) = &self.squares[square] {
+                if let Some(piece) = &self.squares[square_at(file, rank)] {
                     if empty_spaces > 0 {
                         fen.push_str(&empty_spaces.to_string());
+                        empty_spaces = 0;
                     }
                     fen.push(Board::piece_to_char(piece));    
                 } else {
@@ -128,7 +361,7 @@ impl Board {
                 fen.push_str(&empty_spaces.to_string());
             }
 
-            if rank < 7 {
+            if rank > 0 {
                 fen.push('/');
             }
         }
@@ -145,25 +378,12 @@ impl Board {
 
         fen.push(' ' );
 
-        let mut castling = String::new();
-        if self.castling_rights & WHITE_KINGSIDE_CASTLING_RIGHTS != 0 { castling.push('K'); }
-        if self.castling_rights & WHITE_QUEENSIDE_CASTLING_RIGHTS != 0 { castling.push('Q'); }
-        if self.castling_rights & BLACK_KINGSIDE_CASTLING_RIGHTS != 0 { castling.push('k'); }
-        if self.castling_rights & BLACK_QUEENSIDE_CASTLING_RIGHTS != 0 { castling.push('q'); }
-
-        if castling.is_empty() {
-            fen.push('-');
-        } else {
-            fen.push_str(&castling);
-        }
+        fen.push_str(&castling);
 
         fen.push(' ' );
 
         if let Some(square) = self.en_passant_square {
-            let file = (square % 8) as u8 + b'a';
-            let rank = (square / 8) as u8 + b'1';
-            fen.push(file as char);
-            fen.push(rank as char);
+            fen.push_str(&Self::square_to_string(square));
         } else {
             fen.push('-');
         }
@@ -180,24 +400,237 @@ impl Board {
     }
 
     pub fn string_to_square(coordinates: &str) -> Result<u8, String> {
-        if coordinates.len() != 2 {
-            return Err(format!("Invalid coordinate {}", coordinates));
+        Ok(Self::str_to_square(coordinates)? as u8)
+    }
+
+    pub fn square_to_string(square: u8) -> String {
+        let file = file_of(square as usize) as u8 + b'a';
+        let rank = rank_of(square as usize) as u8 + b'1';
+        format!("{}{}", file as char, rank as char)
+    }
+
+}
+/* EPD is what test suites (WAC, STS, Bratko-Kopec) come in: the first four FEN fields, no
+clocks, then operations "opcode operand operand;" like
+    2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";
+The opcodes used here:
+bm / am    best moves / moves to avoid, in SAN
+id         the name of the position
+c0 - c9    comments
+ce         evaluation in centipawns for the side to move
+pv         a line of moves in SAN
+dm         mate in that many moves
+hmvc, fmvn the halfmove clock and fullmove number FEN would have
+Any other opcode is kept as it is so writing the line again doesn't lose it.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Epd {
+    pub board: Board,
+    pub operations: Vec<(String, Vec<String>)>, // in the order they were written, operands without quotes
+    pub best_moves: Vec<Move>, // bm
+    pub avoid_moves: Vec<Move>, // am
+}
+
+impl Epd {
+    pub fn new(board: Board) -> Self {
+        Epd { board, operations: Vec::new(), best_moves: Vec::new(), avoid_moves: Vec::new() }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, FenError> {
+        // the end of the fourth field, the operations start after it
+        let mut fields = 0;
+        let mut in_field = false;
+        let mut position_end = line.len();
+        for (offset, ch) in line.char_indices() {
+            if ch.is_whitespace() {
+                if in_field {
+                    fields += 1;
+                    if fields == 4 {
+                        position_end = offset;
+                        break;
+                    }
+                }
+                in_field = false;
+            } else {
+                in_field = true;
+            }
+        }
+        if fields < 4 && !(fields == 3 && in_field) {
+            return Err(FenError::FieldCount { found: fields + in_field as usize });
         }
 
-        let chars: Vec<char> = coordinates.chars().collect();
-        let file = chars[0];
-        let rank = chars[1];
+        // the clocks go in as FEN would have them, errors in the first fields keep their offsets
+        let operations = parse_operations(&line[position_end..], position_end)?;
+        let operand = |opcode: &str| operations.iter().find(|(name, _, _)| name == opcode).map(|(_, operands, offset)| (operands, *offset));
+        let clock = |opcode: &str, default: u32| -> Result<u32, FenError> {
+            match operand(opcode) {
+                None => Ok(default),
+                Some((operands, offset)) => operands.first().and_then(|value| value.parse().ok())
+                    .ok_or(FenError::BadOperand { opcode: opcode.to_string(), offset }),
+            }
+        };
+        let fen = format!("{} {} {}", &line[..position_end], clock("hmvc", 0)?, clock("fmvn", 1)?);
+        let mut epd = Epd::new(Board::from_fen(&fen)?);
+
+        for (opcode, operands, offset) in operations {
+            let moves = match opcode.as_str() {
+                "bm" | "am" => operands.iter()
+                    .map(|san| Move::from_san(san, &epd.board))
+                    .collect::<Result<Vec<Move>, String>>()
+                    .map_err(|_| FenError::BadOperand { opcode: opcode.clone(), offset })?,
+                "pv" => epd.line(&operands).map_err(|_| FenError::BadOperand { opcode: opcode.clone(), offset })?.0,
+                "ce" | "dm" if operands.len() != 1 || operands[0].parse::<i32>().is_err() => {
+                    return Err(FenError::BadOperand { opcode, offset });
+                },
+                _ => Vec::new(),
+            };
+            match opcode.as_str() {
+                "bm" => epd.best_moves = moves,
+                "am" => epd.avoid_moves = moves,
+                _ => {}
+            }
+            epd.operations.push((opcode, operands));
+        }
+        Ok(epd)
+    }
 
-        if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
-            return Err("Invalid square".to_string());
+    pub fn operands(&self, opcode: &str) -> Option<&[String]> {
+        self.operations.iter().find(|(name, _)| name == opcode).map(|(_, operands)| operands.as_slice())
+    }
+
+    // replaces the operation if it's there, adds it at the end if it isn't. bm and am have to be legal SAN moves
+    pub fn set_operation(&mut self, opcode: &str, operands: Vec<String>) -> Result<(), String> {
+        if opcode == "bm" || opcode == "am" {
+            let moves = operands.iter().map(|san| Move::from_san(san, &self.board)).collect::<Result<Vec<Move>, String>>()?;
+            if opcode == "bm" {
+                self.best_moves = moves;
+            } else {
+                self.avoid_moves = moves;
+            }
         }
+        match self.operations.iter_mut().find(|(name, _)| name == opcode) {
+            Some(operation) => operation.1 = operands,
+            None => self.operations.push((opcode.to_string(), operands)),
+        }
+        Ok(())
+    }
 
-        let file_index = (file as u8 - b'a') as u8;
-        let rank_index = (rank as u8 - b'1') as u8;
+    pub fn id(&self) -> Option<&str> {
+        self.operands("id")?.first().map(|id| id.as_str())
+    }
 
-        let square = rank_index * 8 + file_index;
-        Ok(square)
+    // c0 to c9
+    pub fn comment(&self, number: u8) -> Option<&str> {
+        self.operands(&format!("c{}", number))?.first().map(|comment| comment.as_str())
+    }
 
+    pub fn centipawns(&self) -> Option<i32> {
+        self.operands("ce")?.first()?.parse().ok()
     }
 
-}
\ No newline at end of file
+    pub fn direct_mate(&self) -> Option<i32> {
+        self.operands("dm")?.first()?.parse().ok()
+    }
+
+    pub fn pv(&self) -> Vec<Move> {
+        self.operands("pv").and_then(|operands| self.line(operands).ok()).map(|(moves, _)| moves).unwrap_or_default()
+    }
+
+    // SAN moves played one after the other from the position
+    fn line(&self, sans: &[String]) -> Result<(Vec<Move>, Board), String> {
+        let mut board = self.board;
+        let mut moves = Vec::new();
+        for san in sans {
+            let movement = Move::from_san(san, &board)?;
+            board.make_move(movement);
+            moves.push(movement);
+        }
+        Ok((moves, board))
+    }
+
+    /* The position without the clocks, then the operations. bm and am are written from the
+    moves so they're always proper SAN, strings are quoted.
+    */
+    pub fn to_epd(&self) -> String {
+        let fen = self.board.to_fen();
+        let mut epd: String = fen.split(' ').take(4).collect::<Vec<_>>().join(" ");
+        for (opcode, operands) in &self.operations {
+            let operands: Vec<String> = match opcode.as_str() {
+                "bm" => self.best_moves.iter().map(|movement| movement.to_san(&self.board)).collect(),
+                "am" => self.avoid_moves.iter().map(|movement| movement.to_san(&self.board)).collect(),
+                _ => operands.iter().map(|operand| {
+                    let is_string = opcode == "id" || (opcode.len() == 2 && opcode.starts_with('c') && opcode != "ce");
+                    if is_string || operand.contains(char::is_whitespace) || operand.contains(';') || operand.is_empty() {
+                        format!("\"{}\"", operand)
+                    } else {
+                        operand.clone()
+                    }
+                }).collect(),
+            };
+            epd.push(' ');
+            epd.push_str(opcode);
+            for operand in operands {
+                epd.push(' ');
+                epd.push_str(&operand);
+            }
+            epd.push(';');
+        }
+        epd
+    }
+}
+
+// "bm Qg6; id "WAC.001";" into opcodes, operands and the offset of each opcode in the line
+fn parse_operations(text: &str, start: usize) -> Result<Vec<(String, Vec<String>, usize)>, FenError> {
+    let mut operations = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    loop {
+        while chars.peek().is_some_and(|(_, ch)| ch.is_whitespace()) {
+            chars.next();
+        }
+        let Some(&(opcode_offset, _)) = chars.peek() else { break };
+        let opcode_offset = start + opcode_offset;
+
+        let mut opcode = String::new();
+        while let Some(&(_, ch)) = chars.peek() && !ch.is_whitespace() && ch != ';' {
+            opcode.push(ch);
+            chars.next();
+        }
+        let valid = opcode.chars().next().is_some_and(|ch| ch.is_ascii_alphabetic()) &&
+            opcode.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+        if !valid {
+            return Err(FenError::BadOpcode { offset: opcode_offset });
+        }
+
+        let mut operands = Vec::new();
+        loop {
+            while chars.peek().is_some_and(|(_, ch)| ch.is_whitespace()) {
+                chars.next();
+            }
+            match chars.next() {
+                Some((_, ';')) => break,
+                None => return Err(FenError::BadOperand { opcode, offset: opcode_offset }),
+                Some((_, '"')) => {
+                    let mut operand = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, ch)) => operand.push(ch),
+                            None => return Err(FenError::BadOperand { opcode, offset: opcode_offset }),
+                        }
+                    }
+                    operands.push(operand);
+                },
+                Some((_, ch)) => {
+                    let mut operand = ch.to_string();
+                    while let Some(&(_, ch)) = chars.peek() && !ch.is_whitespace() && ch != ';' {
+                        operand.push(ch);
+                        chars.next();
+                    }
+                    operands.push(operand);
+                },
+            }
+        }
+        operations.push((opcode, operands, opcode_offset));
+    }
+    Ok(operations)
+}
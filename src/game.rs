@@ -0,0 +1,124 @@
+use crate::board::{Board, UndoInfo};
+use crate::movegen::Move;
+use crate::status::GameStatus;
+
+/* A game is the starting position plus the moves played from it. Board only knows the current
+position, Game remembers how it got there: the undo information to take moves back, the keys
+of the earlier positions for the repetition draws, and the moves themselves for writing the
+game down.
+Taking a move back doesn't forget it, it can be played again with redo() until a different
+move is pushed, like the back and forward buttons of a GUI.
+*/
+#[derive(Clone)]
+pub struct Game {
+    start: Board,
+    board: Board,
+    moves: Vec<Move>, // the whole line, also the moves after the current ply that can be redone
+    undo_stack: Vec<UndoInfo>, // one per move played up to the current ply
+    keys: Vec<u64>, // keys of the positions before each of those moves
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new(Board::default())
+    }
+}
+
+impl Game {
+    pub fn new(start: Board) -> Self {
+        Game {
+            start,
+            board: start,
+            moves: Vec::new(),
+            undo_stack: Vec::new(),
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        Ok(Self::new(Board::from_fen(fen)?))
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn start_position(&self) -> &Board {
+        &self.start
+    }
+
+    // how many moves are played to get to the current position
+    pub fn ply(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    // the moves from the start to the current position
+    pub fn moves(&self) -> &[Move] {
+        &self.moves[..self.ply()]
+    }
+
+    // the moves taken back that redo() can play again
+    pub fn redo_moves(&self) -> &[Move] {
+        &self.moves[self.ply()..]
+    }
+
+    // the keys of every position before the current one, oldest first
+    pub fn history(&self) -> &[u64] {
+        &self.keys
+    }
+
+    pub fn status(&self) -> GameStatus {
+        self.board.status(&self.keys)
+    }
+
+    // plays a move, the caller makes sure it is legal. Forgets the moves that could be redone
+    pub fn push(&mut self, movement: Move) {
+        self.moves.truncate(self.ply());
+        self.moves.push(movement);
+        self.play_next();
+    }
+
+    // parses the move in the current position and plays it
+    pub fn push_str(&mut self, movestring: &str) -> Result<Move, String> {
+        let movement = Move::string_to_move(movestring, &self.board)?;
+        self.push(movement);
+        Ok(movement)
+    }
+
+    // takes back the last move
+    pub fn pop(&mut self) -> Option<Move> {
+        let undo_info = self.undo_stack.pop()?;
+        let movement = self.moves[self.ply()];
+        self.keys.pop();
+        self.board.unmake_move(movement, undo_info);
+        Some(movement)
+    }
+
+    // plays again the last move taken back
+    pub fn redo(&mut self) -> Option<Move> {
+        let movement = *self.moves.get(self.ply())?;
+        self.play_next();
+        Some(movement)
+    }
+
+    // goes back or forward along the line to the position after the given number of moves
+    pub fn jump_to(&mut self, ply: usize) -> Result<(), String> {
+        if ply > self.moves.len() {
+            return Err(format!("ply {} is past the end of the game ({} moves)", ply, self.moves.len()));
+        }
+        while self.ply() > ply {
+            self.pop();
+        }
+        while self.ply() < ply {
+            self.play_next();
+        }
+        Ok(())
+    }
+
+    fn play_next(&mut self) {
+        let movement = self.moves[self.ply()];
+        self.keys.push(self.board.hash);
+        let undo_info = self.board.make_move(movement);
+        self.undo_stack.push(undo_info);
+    }
+}
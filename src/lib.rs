@@ -1,5 +1,19 @@
 pub mod board;
+pub mod book;
 pub mod movegen;
 pub mod search;
 pub mod eval;
-pub mod fen;
\ No newline at end of file
+pub mod fen;
+pub mod uci;
+pub mod xboard;
+pub mod zobrist;
+pub mod tt;
+pub mod ordering;
+pub mod perft;
+pub mod status;
+pub mod bitboard;
+pub mod game;
+pub mod validate;
+pub mod san;
+pub mod pgn;
+pub mod testsuite;
@@ -1,76 +1,332 @@
-use std::io;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::process;
+use std::sync::atomic::AtomicBool;
 use std::thread::sleep;
-use std::time;
+use std::time::{self, Duration, Instant, SystemTime};
 
-use board::Color;
-use board::Board;
-use movegen::Move;
+use chess_engine_rust::board::Color;
+use chess_engine_rust::board::Board;
+use chess_engine_rust::book::{BookBuilder, BookMode, OpeningBook};
+use chess_engine_rust::fen::Epd;
+use chess_engine_rust::game::Game;
+use chess_engine_rust::perft;
+use chess_engine_rust::pgn::PgnGame;
+use chess_engine_rust::search::{self, SearchLimits};
+use chess_engine_rust::status::{GameStatus, Outcome};
+use chess_engine_rust::testsuite;
+use chess_engine_rust::tt::TranspositionTable;
+use chess_engine_rust::uci::Uci;
+use chess_engine_rust::xboard::XBoard;
 
-mod board;
-mod movegen;
-mod search;
-mod eval;
+const ENGINE_MOVE_TIME: Duration = Duration::from_secs(2);
+const TESTSUITE_MOVE_TIME: Duration = Duration::from_secs(1);
+const ENGINE_NAME: &str = "rusty-chess-engine";
 
 fn main() {
-    let mut board = Board::default();
+    // "perft <depth> [fen]" and "divide <depth> [fen]" count moves instead of playing
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(command) = args.first() && (command == "perft" || command == "divide") {
+        if let Err(e) = run_perft(command == "divide", &args[1..]) {
+            eprintln!("{}", e);
+            eprintln!("usage: {} <depth> [fen]", command);
+            process::exit(1);
+        }
+        return;
+    }
+    // "testsuite <file.epd> [--time <ms> | --depth <n>] [--json]" scores the engine on a test suite
+    if let Some(command) = args.first() && command == "testsuite" {
+        if let Err(e) = run_testsuite(&args[1..]) {
+            eprintln!("{}", e);
+            eprintln!("usage: testsuite <file.epd> [--time <ms> | --depth <n>] [--json]");
+            process::exit(1);
+        }
+        return;
+    }
+
+    // "makebook <out.bin> <games.pgn>..." turns PGN files into a Polyglot book
+    if let Some(command) = args.first() && command == "makebook" {
+        if let Err(e) = run_makebook(&args[1..]) {
+            eprintln!("{}", e);
+            eprintln!("usage: makebook <out.bin> <games.pgn>... [--min-games <n>] [--max-ply <n>] [--color white|black]");
+            process::exit(1);
+        }
+        return;
+    }
+
+    // "--pgn <file>" adds every finished game to the file
+    let pgn_file = args.iter().position(|arg| arg == "--pgn").and_then(|i| args.get(i + 1)).cloned();
 
-    board.print_board();
+    let book = match load_book(&args) {
+        Ok(book) => book,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: --book <file.bin> [--book-depth <moves>] [--book-best]");
+            process::exit(1);
+        },
+    };
+
+    let mut game = Game::default();
+
+    let mut tt = TranspositionTable::default();
+
+    game.board().print_board();
     let player = select_color();
+    let mut draw_claimed = false;
 
     loop {
-        if board.side_to_move == player {
-            let mut movement = String::new(); 
-            io::stdin().read_line(&mut movement).unwrap();
-            if let Ok(user_move) = Move::string_to_move(&movement.trim(), &board) {
-                board.make_move(user_move);
-                board.side_to_move = board.side_to_move.opposite();
-            } else {
+        if game.board().side_to_move == player {
+            let mut movement = String::new();
+            if io::stdin().read_line(&mut movement).unwrap() == 0 {
+                break;
+            }
+
+            // a GUI starts with "uci" or "xboard", from then on it drives the engine
+            match movement.trim() {
+                "uci" => {
+                    Uci::new().run();
+                    return;
+                },
+                "xboard" => {
+                    XBoard::new().run();
+                    return;
+                },
+                // takes back the engine's move and ours
+                "undo" => {
+                    game.pop();
+                    if game.board().side_to_move != player {
+                        game.pop();
+                    }
+                    game.board().print_board();
+                    continue;
+                },
+                // threefold repetition and the 50-move rule only end the game when claimed
+                "draw" if matches!(game.status(), GameStatus::Claimable(_)) => draw_claimed = true,
+                "draw" => {
+                    println!("There is no draw to claim");
+                    continue;
+                },
+                _ => {}
+            }
+
+            if !draw_claimed && game.push_str(movement.trim()).is_err() {
                 println!("Invalid move");
                 continue;
             }
+        } else if let Some(movement) = book.as_ref().and_then(|book| book.pick(game.board())) {
+            println!("Move made by engine: {} (book)", movement.to_san(game.board()));
+            game.push(movement);
         } else {
 
-            let movement = search::minimax_best_move(&board, 4);
-            let mut movestring = String::new();
-            if movement.is_none() {
-                //check or stalemate, there are no moves left
-                if board.is_check() {
-                    board.print_board();
-                    println!("{} loses by checkmate!", board.side_to_move.to_string());
-                    break;
-                } else {
-                    board.print_board();
-                    println!("Stalemate!");
-                    break;
+            let stop = AtomicBool::new(false);
+            let limits = SearchLimits::move_time(ENGINE_MOVE_TIME);
+            let Some(result) = search::iterative_deepening(game.board(), &limits, &stop, &mut tt, |_, _, _| {}) else {
+                // can't happen, the game would be over already
+                break;
+            };
+
+            let movestring = result.best_move.to_san(game.board());
+            game.push(result.best_move);
+            println!("Move made by engine: {}", movestring);
+        }
+
+        game.board().print_board();
+
+        let outcome = match game.status() {
+            GameStatus::Over(outcome) => Some(outcome),
+            GameStatus::Claimable(reason) if draw_claimed => Some(Outcome::draw(reason)),
+            GameStatus::Claimable(reason) if game.board().side_to_move == player => {
+                println!("You can claim a draw by {}, type draw to do it", reason);
+                None
+            },
+            _ => None,
+        };
+        if let Some(outcome) = outcome {
+            println!("{}! {}", outcome, outcome.result_string());
+            if let Some(path) = &pgn_file {
+                let mut pgn = PgnGame::from_game(&game);
+                pgn.result = outcome.result_string().to_string();
+                pgn.set_tag("Event", "Casual game");
+                pgn.set_tag("Date", &today());
+                pgn.set_tag("Round", "-");
+                let (white, black) = if player == Color::White { ("Player", ENGINE_NAME) } else { (ENGINE_NAME, "Player") };
+                pgn.set_tag("White", white);
+                pgn.set_tag("Black", black);
+                if let Some(&last) = pgn.mainline().last() {
+                    pgn.nodes[last].comments.push(outcome.to_string());
+                }
+                if let Err(e) = save_pgn(path, &pgn) {
+                    eprintln!("couldn't save the game to {}: {}", path, e);
                 }
             }
+            break;
+        }
 
-            if board.halfmove_clock >= 50 {
-                board.print_board();
-                println!("Draw by 50 move rule!");
-                break;
-            }
+        sleep(time::Duration::from_millis(100));
+    }
 
-            movestring = movement.unwrap().to_string(&board);
-            board.make_move(movement.unwrap());
-            println!("Move made by engine: {}", movestring);
-            board.side_to_move = board.side_to_move.opposite();
+}
+
+// "--book <file.bin>" plays from a Polyglot book while it has moves
+fn load_book(args: &[String]) -> Result<Option<OpeningBook>, String> {
+    let option = |name: &str| args.iter().position(|arg| arg == name).map(|i| args.get(i + 1).ok_or(format!("missing value for {}", name)));
+    let Some(path) = option("--book") else { return Ok(None) };
+    let mut book = OpeningBook::open(path?)?;
+    if let Some(depth) = option("--book-depth") {
+        book.max_depth = depth?.parse().map_err(|_| "invalid book depth".to_string())?;
+    }
+    if args.iter().any(|arg| arg == "--book-best") {
+        book.mode = BookMode::Best;
+    }
+    Ok(Some(book))
+}
+
+fn run_makebook(args: &[String]) -> Result<(), String> {
+    let output = args.first().ok_or("missing output file")?;
+    let mut builder_options = Vec::new();
+    let mut pgn_files = Vec::new();
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--min-games" | "--max-ply" | "--color" => builder_options.push((arg.as_str(), iter.next().ok_or(format!("missing value for {}", arg))?)),
+            _ => pgn_files.push(arg),
         }
+    }
+    if pgn_files.is_empty() {
+        return Err("no PGN files".to_string());
+    }
 
-            
-        if board.side_to_move == Color::White {
-            board.fullmove_number += 1
+    let mut builder = BookBuilder::new();
+    for (option, value) in builder_options {
+        match option {
+            "--min-games" => builder.min_games = value.parse().map_err(|_| format!("invalid minimum games {}", value))?,
+            "--max-ply" => builder.max_ply = value.parse().map_err(|_| format!("invalid maximum ply {}", value))?,
+            _ => builder.color = Some(match value.as_str() {
+                "white" => Color::White,
+                "black" => Color::Black,
+                _ => return Err(format!("invalid color {}", value)),
+            }),
         }
+    }
 
+    let mut games = 0;
+    for path in pgn_files {
+        let file = File::open(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        let (added, errors) = builder.add_pgn(BufReader::new(file));
+        // a few broken games shouldn't cost the whole collection
+        for e in errors {
+            eprintln!("{}: {}", path, e);
+        }
+        games += added;
+    }
 
-        board.print_board();
+    let mut file = File::create(output).map_err(|e| format!("can't create {}: {}", output, e))?;
+    let entries = builder.write(&mut file).map_err(|e| format!("can't write {}: {}", output, e))?;
+    println!("Games: {}", games);
+    println!("Entries: {}", entries);
+    Ok(())
+}
 
+fn run_perft(divide: bool, args: &[String]) -> Result<(), String> {
+    let depth: u8 = args.first()
+        .ok_or("missing depth")?
+        .parse()
+        .map_err(|_| "invalid depth".to_string())?;
+    let board = if args.len() > 1 {
+        Board::from_fen(&args[1..].join(" "))?
+    } else {
+        Board::default()
+    };
 
-        sleep(time::Duration::from_millis(100));
+    let start = Instant::now();
+    let nodes = if divide {
+        let counts = perft::divide(&board, depth);
+        for (movement, count) in &counts {
+            println!("{}: {}", movement.to_uci_for(&board), count);
+        }
+        println!();
+        println!("Moves: {}", counts.len());
+        counts.iter().map(|(_, count)| count).sum()
+    } else {
+        perft::perft(&board, depth)
+    };
+    let elapsed = start.elapsed();
+
+    println!("Nodes: {}", nodes);
+    println!("Time: {} ms", elapsed.as_millis());
+    println!("NPS: {}", (nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64);
+    Ok(())
+}
+
+fn run_testsuite(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or("missing EPD file")?;
+    let option = |name: &str| args.iter().position(|arg| arg == name).map(|i| args.get(i + 1).ok_or(format!("missing value for {}", name)));
+    let limits = if let Some(depth) = option("--depth") {
+        SearchLimits::depth(depth?.parse().map_err(|_| "invalid depth".to_string())?)
+    } else if let Some(time) = option("--time") {
+        SearchLimits::move_time(Duration::from_millis(time?.parse().map_err(|_| "invalid time".to_string())?))
+    } else {
+        SearchLimits::move_time(TESTSUITE_MOVE_TIME)
+    };
+    let json = args.iter().any(|arg| arg == "--json");
+
+    let text = std::fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let mut positions = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // a broken line shouldn't throw away the whole suite
+        match Epd::parse(line) {
+            Ok(epd) => positions.push(epd),
+            Err(e) => eprintln!("{}:{}: {}", path, number + 1, e),
+        }
+    }
+
+    let mut tt = TranspositionTable::default();
+    if !json {
+        println!("{}", testsuite::table_header());
+    }
+    let results = testsuite::run_suite(&positions, &limits, &mut tt, |result| {
+        if !json {
+            println!("{}", testsuite::table_row(result));
+        }
+    });
+    let summary = testsuite::summarize(&results);
+    if json {
+        println!("{}", testsuite::to_json(&results, &summary));
+    } else {
+        println!();
+        println!("{}", testsuite::summary_text(&summary));
     }
+    Ok(())
+}
 
+fn save_pgn(path: &str, pgn: &PgnGame) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(pgn.to_pgn().as_bytes())
+}
+
+// the date for the PGN Date tag, YYYY.MM.DD in UTC
+fn today() -> String {
+    let Ok(since_epoch) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) else {
+        return "????.??.??".to_string();
+    };
+    // days to a civil date, http://howardhinnant.github.io/date_algorithms.html
+    let days = (since_epoch.as_secs() / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
 }
 
 pub fn select_color() -> Color {
     Color::White
-}
\ No newline at end of file
+}
@@ -1,36 +1,31 @@
-use crate::board;
 use crate::board::Board;
 use crate::board::Color;
 use crate::board::Piece;
+use crate::board::{rank_of, square_at, BLACK_KINGSIDE_CASTLING_RIGHTS, CASTLING_RIGHTS, WHITE_KINGSIDE_CASTLING_RIGHTS};
+use crate::bitboard::{self, bishop_attacks, color_index, queen_attacks, rook_attacks, square_bb};
+use crate::bitboard::{BETWEEN, FILE_A, FILE_H, KING_ATTACKS, LINE, KNIGHT_ATTACKS, PAWN_ATTACKS, RANK_1, RANK_4, RANK_5, RANK_8};
 
 
 pub const FROM_MASK:u16 = 0x3F;
 pub const TO_MASK:u16 = 0x3F << 6;
 pub const FLAG_MASK: u16 = 0xF << 12;    
 
-const FLAG_NORMAL: u16 = 0;
-const FLAG_CAPTURE: u16 = 1;
-const FLAG_EP_CAPTURE: u16 = 2;
-const FLAG_CASTLE_KING: u16 = 3;
-const FLAG_CASTLE_QUEEN: u16 = 4;
-const FLAG_PROMOTION_QUEEN: u16 = 5;
-const FLAG_PROMOTION_ROOK: u16 = 6;
-const FLAG_PROMOTION_BISHOP: u16 = 7;
-const FLAG_PROMOTION_KNIGHT: u16 = 8;
-const FLAG_PROMOTION_CAPTURE_QUEEN: u16 = 9;
-const FLAG_PROMOTION_CAPTURE_ROOK: u16 = 10;
-const FLAG_PROMOTION_CAPTURE_BISHOP: u16 = 11;
-const FLAG_PROMOTION_CAPTURE_KNIGHT: u16 = 12;
+pub const FLAG_NORMAL: u16 = 0;
+pub const FLAG_CAPTURE: u16 = 1;
+pub const FLAG_EP_CAPTURE: u16 = 2;
+pub const FLAG_CASTLE_KING: u16 = 3;
+pub const FLAG_CASTLE_QUEEN: u16 = 4;
+pub const FLAG_PROMOTION_QUEEN: u16 = 5;
+pub const FLAG_PROMOTION_ROOK: u16 = 6;
+pub const FLAG_PROMOTION_BISHOP: u16 = 7;
+pub const FLAG_PROMOTION_KNIGHT: u16 = 8;
+pub const FLAG_PROMOTION_CAPTURE_QUEEN: u16 = 9;
+pub const FLAG_PROMOTION_CAPTURE_ROOK: u16 = 10;
+pub const FLAG_PROMOTION_CAPTURE_BISHOP: u16 = 11;
+pub const FLAG_PROMOTION_CAPTURE_KNIGHT: u16 = 12;
 
 
 
-const KNIGHT_DIRS: [(i32, i32); 8]= [(1, 2), (2, 1), (-2, 1), (-1, 2), (1, -2), (2, -1), (-2, -1), (-1, -2)];
-const ROOK_DIRS: [(i32, i32); 4]= [(1, 0), (-1, 0), (0, 1), (0, -1)];
-const BISHOP_DIRS: [(i32, i32); 4]= [(1, 1), (1, -1), (-1, 1), (-1, -1)];
-const WHITE_PAWN_CAPTURES: [(i32, i32); 2]= [(1,1), (-1, 1)];
-const BLACK_PAWN_CAPTURES: [(i32, i32); 2]= [(-1,-1), (1, -1)];
-
-
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Move {
     data: u16, // 6 bits from, 6 bits to, 4 bits special flags (castling, en-passant, check, promotion, piece to promote to)
@@ -96,7 +91,7 @@ impl Move {
     pub fn is_capture(&self) -> bool {
         let flag:u16 = self.get_flag();
         flag == FLAG_CAPTURE || flag == FLAG_EP_CAPTURE || 
-        (flag >= FLAG_PROMOTION_CAPTURE_QUEEN && flag <= FLAG_PROMOTION_CAPTURE_KNIGHT)
+        (FLAG_PROMOTION_CAPTURE_QUEEN..=FLAG_PROMOTION_CAPTURE_KNIGHT).contains(&flag)
     }
 
     pub fn is_en_passant(&self) -> bool {
@@ -105,7 +100,7 @@ impl Move {
 
     pub fn is_promotion(&self) -> bool {
         let flag:u16 = self.get_flag();
-        flag >= FLAG_PROMOTION_QUEEN && flag <= FLAG_PROMOTION_CAPTURE_KNIGHT
+        (FLAG_PROMOTION_QUEEN..=FLAG_PROMOTION_CAPTURE_KNIGHT).contains(&flag)
     }
 
     pub fn is_castle_kingside(&self) -> bool {
@@ -122,13 +117,27 @@ impl Move {
         self.is_castle_kingside() || self.is_castle_queenside()
     }
 
+    /* Castling moves go from the king to the rook it castles with ("king takes rook"), in
+    Chess960 that's the only way to tell them apart from a normal king move. Wherever they start,
+    king and rook end up on the same squares as in normal chess: g and f files on the kingside,
+    c and d on the queenside. Returns (king destination, rook destination).
+    */
+    pub fn castling_destinations(&self) -> (usize, usize) {
+        let rank = rank_of(self.get_from() as usize);
+        if self.is_castle_kingside() {
+            (square_at(6, rank), square_at(5, rank))
+        } else {
+            (square_at(2, rank), square_at(3, rank))
+        }
+    }
+
     pub fn promotion_piece(&self) -> Option<u16> {
         if !self.is_promotion() {
             return None;
         }
 
         let flag = self.get_flag();
-        if flag >= FLAG_PROMOTION_CAPTURE_QUEEN && flag <= FLAG_PROMOTION_CAPTURE_KNIGHT {
+        if (FLAG_PROMOTION_CAPTURE_QUEEN..=FLAG_PROMOTION_CAPTURE_KNIGHT).contains(&flag) {
             Some(flag - FLAG_PROMOTION_CAPTURE_QUEEN)
         } else {
             Some(flag - FLAG_PROMOTION_QUEEN)
@@ -148,101 +157,44 @@ impl Move {
         }
     }
 
-    pub fn to_string(&self, board: &Board) -> String {
-        let from_file:u16 = self.get_from() % 8;
-
-        let rank:u16 = self.get_to() / 8 + 1;
-        let file:u16 = self.get_to() % 8;
-        let to:String = format!("{}{}", (b'a' + file as u8) as char, rank);
-        
-        let moving_piece = Board::piece_to_char(&board.squares[self.get_from() as usize].unwrap()).to_ascii_uppercase();
-
-        
-        let piece_to_promote = match self.promotion_piece() {
-            Some(0) => Board::piece_to_char(&Piece::Queen(board.side_to_move)).to_ascii_uppercase().to_string(),
-            Some(1) => Board::piece_to_char(&Piece::Rook(board.side_to_move)).to_ascii_uppercase().to_string(),
-            Some(2) => Board::piece_to_char(&Piece::Bishop(board.side_to_move)).to_ascii_uppercase().to_string(),
-            Some(3) => Board::piece_to_char(&Piece::Knight(board.side_to_move)).to_ascii_uppercase().to_string(),
-            _ => String::new() 
-        }; 
-        
-        let disambiguation = self.disambiguation(&board);
-
-        let movestring = match self.get_move_type() {
-            MoveType::Capture | MoveType::EnPassant => {
-                if moving_piece == 'P' || moving_piece == 'p' {
-                    format!("{}x{}", (b'a' + from_file as u8) as char, to)
-                } else {
-                    format!("{}{}x{}", moving_piece, disambiguation, to)
-                }
-            }, 
-            MoveType::CastleKingside => "0-0".to_string(), 
-            MoveType::CastleQueenside => "0-0-0".to_string(),
-            MoveType::Promotion => format!("{}{}={}", moving_piece, to, piece_to_promote),
-            MoveType::PromotionCapture => format!("{}x{}={}", (b'a' + from_file as u8), to, piece_to_promote),
-            MoveType::Normal => {
-                if moving_piece == 'P' || moving_piece == 'p' {
-                    format!("{}", to)
-                } else {
-                    format!("{}{}{}", moving_piece, disambiguation, to)
-                }
+    // coordinate notation used by UCI, e.g. e2e4 or e7e8q, castling is written as the king's move (e1g1)
+    pub fn to_uci(&self) -> String {
+        let to = if self.is_castle() { self.castling_destinations().0 as u16 } else { self.get_to() };
+        self.coordinates(to)
+    }
 
-            },
-        };
+    // UCI in Chess960 mode, where castling is the king taking its rook (e1h1)
+    pub fn to_uci_chess960(&self) -> String {
+        self.coordinates(self.get_to())
+    }
 
-        movestring
-    }
-
-    pub fn disambiguation(&self, mut board: &Board) -> String {
-        let from_rank:u16 = self.get_from() / 8 + 1;
-        let from_file:u16 = self.get_from() % 8;
-        let from:u16= self.get_from(); 
-        let to_square:u16 = self.get_to(); 
-        
-        let mut same_file = false;
-        let mut same_rank = false;
-        let mut needs_disambiguation = false;
-        let moving_piece = &board.squares[self.get_from() as usize].unwrap();
-
-        let legal_moves = generate_moves(&mut board);
-
-        for movement in legal_moves {
-            if movement.get_to() == to_square && movement.get_from() != from {
-                if let Some(piece) = board.squares[movement.get_from() as usize] {
-                    if Board::piece_to_char(&piece) == Board::piece_to_char(&moving_piece) {
-                        needs_disambiguation = true;
-                        let other_file = movement.get_from() % 8;
-                        let other_rank = movement.get_from() / 8;
-                        
-                        if other_file == from_file {
-                            same_file = true;
-                        }
-                        if other_rank == from_rank {
-                            same_rank = true;
-                        }
+    // picks the right one for the board
+    pub fn to_uci_for(&self, board: &Board) -> String {
+        if board.chess960 { self.to_uci_chess960() } else { self.to_uci() }
+    }
 
-                    }
-                }
-            }
-        }
-        if !needs_disambiguation {
-            return String::new();
-        }
-         if !same_file {
-            format!("{}", (b'a' + from_file as u8) as char)
-        } else if !same_rank {
-            format!("{}", from_rank + 1)
-        } else {
-            format!("{}{}", (b'a' + from_file as u8) as char, from_rank + 1)
-        }
+    fn coordinates(&self, to: u16) -> String {
+        let promotion = match self.promotion_piece() {
+            Some(0) => "q",
+            Some(1) => "r",
+            Some(2) => "b",
+            Some(3) => "n",
+            _ => ""
+        };
+        format!("{}{}{}", Board::square_to_string(self.get_from() as u8), Board::square_to_string(to as u8), promotion)
     }
-    
-    pub fn string_to_move(input: &str, board: &Board) -> Result<Move, String> {
-        let input = input.trim().to_lowercase();
 
-        if input.len() != 4 && input.len() != 5 {
-            return Err("move length is not correct".to_string());
+    // coordinate notation (e2e4, e7e8q) or SAN (e4, Nf3, O-O), see san.rs
+    pub fn string_to_move(input: &str, board: &Board) -> Result<Move, String> {
+        let input = input.trim();
+        let bytes = input.as_bytes();
+        let coordinates = (input.len() == 4 || input.len() == 5) &&
+            (b'a'..=b'h').contains(&bytes[0]) && (b'1'..=b'8').contains(&bytes[1]) &&
+            (b'a'..=b'h').contains(&bytes[2]) && (b'1'..=b'8').contains(&bytes[3]);
+        if !coordinates {
+            return Move::from_san(input, board);
         }
+        let input = input.to_lowercase();
 
         let origin = &input[..2];
         let destination = &input[2..4];
@@ -262,8 +214,10 @@ impl Move {
         let square_from = Board::str_to_square(origin)?;
         let square_to = Board::str_to_square(destination)?;
 
-        for movement in generate_moves(&board) {
-            if movement.get_from() == square_from as u16 && movement.get_to() == square_to as u16 {
+        for movement in generate_moves(board) {
+            // outside Chess960 castling can also be written as the king's move, e1g1
+            let castle_to_king = !board.chess960 && movement.is_castle() && movement.castling_destinations().0 == square_to;
+            if movement.get_from() == square_from as u16 && (movement.get_to() == square_to as u16 || castle_to_king) {
                 if movement.is_promotion() {
                     if movement.promotion_piece().unwrap() as i16 == promo_piece {
                         return Ok(movement);
@@ -275,388 +229,295 @@ impl Move {
             }
         }
 
-        return Err("movement not found".to_string());
+        Err("movement not found".to_string())
     }
 
 }
 
 
 pub fn generate_moves(board: &Board) -> Vec<Move> {
-    let mut moves:Vec<Move> = Vec::new();
+    let mut moves:Vec<Move> = Vec::with_capacity(64);
 
-    generate_all_moves(board, &mut moves);
-    filter(board, moves)
+    generate_legal_moves(board, false, &mut moves);
+    moves
 }
 
+// only the legal captures and promotions, what the quiescence search looks at
+pub fn generate_captures(board: &Board) -> Vec<Move> {
+    let mut moves:Vec<Move> = Vec::with_capacity(16);
 
-fn generate_all_moves(board: &Board, moves: &mut Vec<Move>) {
-    for square in 0..64 {
-        if let Some(piece) = board.squares[square] {
-            match piece {
-                Piece::Pawn(color) if color == board.side_to_move => {
-                    generate_one_pawn_moves(board, square as u16, moves)
-                }
-                Piece::Rook(color) if color == board.side_to_move => {
-                    generate_one_rook_moves(board, square as u16, moves)
-                }
-                Piece::Bishop(color) if color == board.side_to_move => {
-                    generate_one_bishop_moves(board, square as u16, moves)
-                }
-                Piece::Knight(color) if color == board.side_to_move => {
-                    generate_one_knight_moves(board, square as u16, moves)
-                }
-                Piece::Queen(color) if color == board.side_to_move => {
-                    generate_queen_moves(board, square as u16, moves)
-                }
-                Piece::King(color) if color == board.side_to_move => {
-                    generate_king_moves(board, square as u16, moves)
-                }
-                _ => {} 
-            }
-        }
-    }
+    generate_legal_moves(board, true, &mut moves);
+    moves
 }
 
+/* Instead of playing every move and looking if the king ends up attacked, the checks and pins
+are worked out once per position:
+- the pieces giving check. With two of them only the king can move, with one the other pieces
+  can only capture it or block the line between it and the king (the check mask)
+- the pinned pieces, the only thing between the king and an enemy slider, can only move along
+  that line
+- the king can't step on an attacked square, looking at the attacks as if the king wasn't on
+  the board, otherwise it could step back along the line of a slider checking it
+En passant is the odd one, it removes two pieces from a rank at once, so it is checked by
+looking at the sliders after the capture.
+For the captures only the enemy pieces are targets, plus the pawn pushes that promote.
+*/
+struct Legality {
+    check_mask: u64,
+    pinned: u64,
+    king: usize,
+    checked: bool,
+    targets: u64,
+    captures_only: bool,
+}
 
-fn generate_one_pawn_moves(board: &Board, from: u16, moves: &mut Vec<Move>) {
-    let (direction, start_rank, promote_rank) = match board.side_to_move {
-        Color::White => (8, 1, 7), // white pawns move up 8 spaces (one vertical step)
-        Color::Black => (-8, 6, 0),
+fn generate_legal_moves(board: &Board, captures_only: bool, moves: &mut Vec<Move>) {
+    let us = board.side_to_move;
+    let them = us.opposite();
+    let own = board.color_occupancy(us);
+    let enemy = board.color_occupancy(them);
+    let occupied = own | enemy;
+    let king = board.king_square(us);
+
+    let checkers = board.attackers(king, them, occupied);
+    let targets = if captures_only { enemy } else { !own };
+
+    // king moves, with the king out of the way so sliders see through it
+    let without_king = occupied & !square_bb(king);
+    let king_targets = KING_ATTACKS[king] & targets;
+    let safe = bitboard::squares(king_targets)
+        .filter(|&to| board.attackers(to, them, without_king) == 0)
+        .fold(0, |safe, to| safe | square_bb(to));
+    push_moves(moves, king, safe, enemy);
+
+    if checkers.count_ones() > 1 {
+        return;
+    }
+
+    let check_mask = match checkers {
+        0 => !0,
+        _ => {
+            let checker = checkers.trailing_zeros() as usize;
+            BETWEEN[king][checker] | checkers
+        },
     };
-    let from_u8 = from as u8;
-    let rank:u8 = from_u8/8;
-    let to_i16: i16 = from as i16 + direction;
-
-    if to_i16 >= 0 && to_i16 < 64 {
-        let to = to_i16 as u8;
-        if board.squares[to as usize].is_none() {
-            if rank as i16 == promote_rank - direction/8 {
-                for i in 0..4{
-                    moves.push(Move::promotion(from, to as u16, i, false))
-                }
-            } else {
-                moves.push(Move::normal(from, to as u16));
-                
-                if rank == start_rank {
-                    let double_to = from_u8 as i16 + 2 * direction;
-                    if board.squares[double_to as usize].is_none() {
-                        moves.push(Move::normal(from, double_to as u16));
-                    }
-                }
-            }
-        }
-    }
 
-    for capture_direction in [-1, 1] {
-        let capture_to = (to_i16 + capture_direction) as u8;
-        if capture_to > 63 || capture_to / 8 == rank || capture_to / 8 != to_i16 as u8 / 8{
-            continue;
-        }
-        if let Some(piece) = &board.squares[capture_to as usize] {
-            if is_enemy(*piece, board.side_to_move) {
-                if rank as i16 == promote_rank - direction/8 {
-                    for piece_type in 0..4 {
-                        moves.push(Move::promotion(from, capture_to as u16, piece_type, true));
-                    }
-                } else {
-                    moves.push(Move::capture(from, capture_to as u16));
-                }
-            }
-        }
-        if let Some(en_passant_square) = board.en_passant_square {
-            if capture_to == en_passant_square {
-                moves.push(Move::en_passant_capture(from, capture_to as u16));
-            }
+    // the enemy sliders that would attack the king if our pieces weren't there
+    let diagonal = board.pieces(Piece::Bishop(them)) | board.pieces(Piece::Queen(them));
+    let straight = board.pieces(Piece::Rook(them)) | board.pieces(Piece::Queen(them));
+    let snipers = (bishop_attacks(king, enemy) & diagonal) | (rook_attacks(king, enemy) & straight);
+    let mut pinned = 0;
+    for sniper in bitboard::squares(snipers) {
+        let blockers = BETWEEN[king][sniper] & occupied;
+        if blockers.count_ones() == 1 {
+            pinned |= blockers & own;
         }
     }
-}
-
 
-fn generate_one_rook_moves(board: &Board, from: u16, moves: &mut Vec<Move>) {
-    generate_long_moves(board, from, moves, &ROOK_DIRS);
+    let legality = Legality { check_mask, pinned, king, checked: checkers != 0, targets, captures_only };
+    generate_piece_moves(board, &legality, moves);
+    generate_pawn_moves(board, &legality, moves);
+    if checkers == 0 && !captures_only {
+        generate_castles(board, king as u16, moves);
+    }
 }
 
+impl Legality {
+    // the squares the piece on from can go to without leaving the king in check
+    fn allowed(&self, from: usize) -> u64 {
+        if self.pinned & square_bb(from) != 0 {
+            self.check_mask & LINE[self.king][from]
+        } else {
+            self.check_mask
+        }
+    }
 
-fn generate_one_bishop_moves(board: &Board, from: u16, moves: &mut Vec<Move>) {
-    generate_long_moves(board, from, moves, &BISHOP_DIRS);
+    fn is_allowed(&self, from: usize, to: usize) -> bool {
+        self.allowed(from) & square_bb(to) != 0
+    }
 }
 
+fn generate_piece_moves(board: &Board, legality: &Legality, moves: &mut Vec<Move>) {
+    let us = board.side_to_move;
+    let enemy = board.color_occupancy(us.opposite());
+    let occupied = board.occupied();
+    let targets = legality.targets;
 
-fn generate_queen_moves(board: &Board, from: u16, moves: &mut Vec<Move>) {
-    generate_long_moves(board, from, moves, &ROOK_DIRS);
-    generate_long_moves(board, from, moves, &BISHOP_DIRS);
+    // a pinned knight can never move, it always leaves the line
+    for from in bitboard::squares(board.pieces(Piece::Knight(us)) & !legality.pinned) {
+        push_moves(moves, from, KNIGHT_ATTACKS[from] & targets & legality.check_mask, enemy);
+    }
+    for from in bitboard::squares(board.pieces(Piece::Bishop(us))) {
+        push_moves(moves, from, bishop_attacks(from, occupied) & targets & legality.allowed(from), enemy);
+    }
+    for from in bitboard::squares(board.pieces(Piece::Rook(us))) {
+        push_moves(moves, from, rook_attacks(from, occupied) & targets & legality.allowed(from), enemy);
+    }
+    for from in bitboard::squares(board.pieces(Piece::Queen(us))) {
+        push_moves(moves, from, queen_attacks(from, occupied) & targets & legality.allowed(from), enemy);
+    }
 }
 
-
-fn generate_long_moves(board: &Board, from: u16, moves: &mut Vec<Move>, direction: &[(i32, i32)]) {
-    let from_u8 = from as u8;
-    let rank:u8 = from_u8 / 8;
-    let file:u8= from_u8 % 8;
-    for &(vx, vy) in direction.iter() {
-        let mut current_rank = rank as i8;
-        let mut current_file = file as i8;
-
-        loop {
-            current_file += vx as i8;
-            current_rank += vy as i8;
-            if current_file > 7 || current_file < 0 || current_rank > 7 || current_rank < 0 {
-                break;
-            }
-            let to = (current_rank * 8 + current_file) as u8;
-
-            match &board.squares[to as usize] {
-                None => {
-                    // Empty square - add normal move
-                    moves.push(Move::normal(from, to as u16));
-                },
-                Some(piece) => {
-                    if is_enemy(*piece, board.side_to_move) {
-                        // capture an enemy piece
-                        moves.push(Move::capture(from, to as u16));
-                    }
-                    
-                    // We hit a piece, so stop exploring this direction
-                    break;
-                }
-            }
+// one move to every square of targets, captures when there is an enemy piece there
+fn push_moves(moves: &mut Vec<Move>, from: usize, targets: u64, enemy: u64) {
+    for to in bitboard::squares(targets) {
+        if enemy & square_bb(to) != 0 {
+            moves.push(Move::capture(from as u16, to as u16));
+        } else {
+            moves.push(Move::normal(from as u16, to as u16));
         }
     }
 }
 
+/* All the pawns move at once: shifting the pawn bitboard one rank forward gives every square
+a pawn can push to, and the square it came from is just 8 behind. Same for the captures, shifted
+diagonally, masking the file that would wrap around to the other side of the board.
+*/
+fn generate_pawn_moves(board: &Board, legality: &Legality, moves: &mut Vec<Move>) {
+    let us = board.side_to_move;
+    let pawns = board.pieces(Piece::Pawn(us));
+    let enemy = board.color_occupancy(us.opposite());
+    let empty = !board.occupied();
+
+    // forward is +8 for white and -8 for black
+    let (forward, double_push_rank, promotion_rank): (i32, u64, u64) = match us {
+        Color::White => (8, RANK_4, RANK_8),
+        Color::Black => (-8, RANK_5, RANK_1),
+    };
+    let shift = |bitboard: u64, by: i32| if by > 0 { bitboard << by } else { bitboard >> -by };
 
-fn generate_king_moves(board: &Board, from: u16, moves: &mut Vec<Move>) {
-    generate_short_moves(board, from, moves, &BISHOP_DIRS);
-    generate_short_moves(board, from, moves, &ROOK_DIRS);
-    generate_castles(board, from, moves);
-}
-
-fn generate_one_knight_moves(board: &Board, from: u16, moves: &mut Vec<Move>) {
-    generate_short_moves(board, from, moves, &KNIGHT_DIRS[0..8]);
-
-}
+    let mut single_pushes = shift(pawns, forward) & empty;
+    let mut double_pushes = shift(single_pushes, forward) & empty & double_push_rank;
+    if legality.captures_only {
+        single_pushes &= promotion_rank;
+        double_pushes = 0;
+    }
+    let left_captures = shift(pawns & !FILE_A, forward - 1) & enemy;
+    let right_captures = shift(pawns & !FILE_H, forward + 1) & enemy;
 
-fn generate_short_moves(board: &Board, from: u16, moves: &mut Vec<Move>, direction: &[(i32, i32)]) {
-    let from_u8 = from as u8;
-    let rank:u8 = from_u8 / 8;
-    let file:u8= from_u8 % 8;
-    for &(vx, vy) in direction.iter() {
-        let mut current_rank = rank as i8;
-        let mut current_file = file as i8;
-                
-        current_file += vx as i8;
-        current_rank += vy as i8;
-        if current_file > 7 || current_file < 0 || current_rank > 7 || current_rank < 0 {
+    for to in bitboard::squares(single_pushes) {
+        let from = (to as i32 - forward) as usize;
+        if !legality.is_allowed(from, to) {
             continue;
         }
-        let to = (current_rank * 8 + current_file) as u8;
-
-        match &board.squares[to as usize] {
-            None => {
-                // Empty square - add normal move
-                moves.push(Move::normal(from, to as u16));
-            },
-            Some(piece) => {
-                // Get the color of the piece
-                let piece_color = match piece {
-                    Piece::Pawn(c) | Piece::Knight(c) | Piece::Bishop(c) |
-                    Piece::Rook(c) | Piece::Queen(c) | Piece::King(c) => *c,
-                };
-                    
-                if piece_color != board.side_to_move {
-                    // capture an enemy piece
-                    moves.push(Move::capture(from, to as u16));
-                }
+        if promotion_rank & square_bb(to) != 0 {
+            for piece_type in 0..4 {
+                moves.push(Move::promotion(from as u16, to as u16, piece_type, false));
             }
-        }                
+        } else {
+            moves.push(Move::normal(from as u16, to as u16));
+        }
     }
-}
-
-fn generate_castles(board: &Board, from: u16, moves: &mut Vec<Move>) {
-    let castling_rights = board.castling_rights;
-    if board.side_to_move == Color::White {
-        if castling_rights & 0b1000 != 0 {
-            let to:u16 = 6;
-            if board.squares[7] == Some(Piece::Rook(board.side_to_move)) {
-                moves.push(Move::castle_kingside(from, to));
+    for to in bitboard::squares(double_pushes) {
+        let from = (to as i32 - 2 * forward) as usize;
+        if legality.is_allowed(from, to) {
+            moves.push(Move::normal(from as u16, to as u16));
+        }
+    }
+    for (captures, offset) in [(left_captures, forward - 1), (right_captures, forward + 1)] {
+        for to in bitboard::squares(captures) {
+            let from = (to as i32 - offset) as usize;
+            if !legality.is_allowed(from, to) {
+                continue;
             }
-        } if castling_rights & 0b0100 != 0 {
-            let to:u16 = 2;
-            if board.squares[0] == Some(Piece::Rook(board.side_to_move)) {
-                moves.push(Move::castle_queenside(from, to));
+            if promotion_rank & square_bb(to) != 0 {
+                for piece_type in 0..4 {
+                    moves.push(Move::promotion(from as u16, to as u16, piece_type, true));
+                }
+            } else {
+                moves.push(Move::capture(from as u16, to as u16));
             }
         }
-    } else {
-        if castling_rights & 0b0010 != 0 {
-            let to:u16 = 62;
-            if board.squares[63] == Some(Piece::Rook(board.side_to_move)) {
-                moves.push(Move::castle_kingside(from, to));
-            }
-        } if castling_rights & 0b0001 != 0 {
-            let to:u16 = 58;
-            if board.squares[56] == Some(Piece::Rook(board.side_to_move)) {
-                moves.push(Move::castle_queenside(from, to));
+    }
+
+    if let Some(en_passant_square) = board.en_passant_square {
+        let to = en_passant_square as usize;
+        let captured = (to as i32 - forward) as usize;
+        // the pawns that could capture on the square are the ones an enemy pawn there would attack
+        let attackers = PAWN_ATTACKS[color_index(us.opposite())][to] & pawns;
+        for from in bitboard::squares(attackers) {
+            if en_passant_is_legal(board, legality, from, to, captured) {
+                moves.push(Move::en_passant_capture(from as u16, to as u16));
             }
         }
     }
 }
 
-fn is_enemy(piece: Piece, side_to_move: Color) -> bool {
-
-    if matches!(piece, Piece::Bishop(c) | Piece::Knight(c) | Piece::Pawn(c) | 
-    Piece::Queen(c) | Piece::Rook(c) | Piece::King(c) if c != side_to_move) {
-        return true;
-    } else {
+// both pawns leave their squares, so just look at what the sliders see once they're gone
+fn en_passant_is_legal(board: &Board, legality: &Legality, from: usize, to: usize, captured: usize) -> bool {
+    // in check, the capture has to block the check or take the pawn giving it
+    if legality.checked && legality.check_mask & (square_bb(to) | square_bb(captured)) == 0 {
         return false;
     }
-}
 
+    let them = board.side_to_move.opposite();
+    let occupied = (board.occupied() & !square_bb(from) & !square_bb(captured)) | square_bb(to);
+    let diagonal = board.pieces(Piece::Bishop(them)) | board.pieces(Piece::Queen(them));
+    let straight = board.pieces(Piece::Rook(them)) | board.pieces(Piece::Queen(them));
+    bishop_attacks(legality.king, occupied) & diagonal == 0 && rook_attacks(legality.king, occupied) & straight == 0
+}
 
-pub fn is_square_attacked(board: &Board, square: u16) -> bool {
-    let attacker_color = match board.side_to_move {
-        Color::Black => Color::White,
-        Color::White => Color::Black,
-    };
-    
-    let rank = (square / 8) as i8;
-    let file = (square % 8) as i8;
-    
-    // Check pawn attacks
-    let pawn_dirs = match attacker_color {
-        Color::White => WHITE_PAWN_CAPTURES,
-        Color::Black => BLACK_PAWN_CAPTURES,   
+/* Only called when not in check, castling out of check is never allowed. Works the same for
+normal chess and Chess960: every square the king and the rook go over or land on has to be
+empty (apart from the king and the rook themselves), and the king can't pass through or land
+on an attacked square. The attacks are looked at without the castling rook, it could be hiding
+an attack along the back rank that hits the king once the rook has moved.
+*/
+fn generate_castles(board: &Board, from: u16, moves: &mut Vec<Move>) {
+    let us = board.side_to_move;
+    let them = us.opposite();
+    let king = from as usize;
+    let rights = match us {
+        Color::White => 0..2,
+        Color::Black => 2..4,
     };
-    
-    for &(dx, dy) in &pawn_dirs {
-        let new_file = file - dx as i8;
-        let new_rank = rank - dy as i8;
-        
-        if new_file >= 0 && new_file < 8 && new_rank >= 0 && new_rank < 8 {
-            let from_square = (new_rank * 8 + new_file) as usize;
-            if let Some(Piece::Pawn(color)) = board.squares[from_square] {
-                if color == attacker_color {
-                    return true;
-                }
-            }
+
+    for index in rights {
+        if board.castling_rights & CASTLING_RIGHTS[index] == 0 {
+            continue;
         }
-    }
-    
-    // Check knight attacks
-    for &(dx, dy) in &KNIGHT_DIRS {
-        let new_file = file + dx as i8;
-        let new_rank = rank + dy as i8;
-        
-        if new_file >= 0 && new_file < 8 && new_rank >= 0 && new_rank < 8 {
-            let from_square = (new_rank * 8 + new_file) as usize;
-            if let Some(Piece::Knight(color)) = board.squares[from_square] {
-                if color == attacker_color {
-                    return true;
-                }
-            }
+        let rook = board.castling_rooks[index] as usize;
+        if board.squares[rook] != Some(Piece::Rook(us)) {
+            continue;
         }
-    }
-    
-    // Check king attacks
-    for &(dx, dy) in BISHOP_DIRS.iter().chain(ROOK_DIRS.iter()) {
-        let new_file = file + dx as i8;
-        let new_rank = rank + dy as i8;
-        
-        if new_file >= 0 && new_file < 8 && new_rank >= 0 && new_rank < 8 {
-            let from_square = (new_rank * 8 + new_file) as usize;
-            if let Some(Piece::King(color)) = board.squares[from_square] {
-                if color == attacker_color {
-                    return true;
-                }
-            }
+        let movement = if CASTLING_RIGHTS[index] & (WHITE_KINGSIDE_CASTLING_RIGHTS | BLACK_KINGSIDE_CASTLING_RIGHTS) != 0 {
+            Move::castle_kingside(from, rook as u16)
+        } else {
+            Move::castle_queenside(from, rook as u16)
+        };
+        let (king_to, rook_to) = movement.castling_destinations();
+
+        let others = board.occupied() & !square_bb(king) & !square_bb(rook);
+        let path = BETWEEN[king][king_to] | square_bb(king_to) | BETWEEN[rook][rook_to] | square_bb(rook_to);
+        if path & others != 0 {
+            continue;
         }
-    }
-    
-    // Check sliding pieces 
-    for &(dx, dy) in ROOK_DIRS.iter().chain(BISHOP_DIRS.iter()) {
-        let is_diagonal = dx != 0 && dy != 0;
-        let is_straight = dx == 0 || dy == 0;
-        
-        let mut new_file = file;
-        let mut new_rank = rank;
-        
-        loop {
-            new_file += dx as i8;
-            new_rank += dy as i8;
-            
-            if new_file < 0 || new_file >= 8 || new_rank < 0 || new_rank >= 8 {
-                break; 
-            }
-            
-            let from_square = (new_rank * 8 + new_file) as usize;
-            
-            if let Some(piece) = &board.squares[from_square] {
-                match piece {
-                    Piece::Queen(color) if *color == attacker_color => return true,
-                    Piece::Rook(color) if *color == attacker_color && is_straight => return true,
-                    Piece::Bishop(color) if *color == attacker_color && is_diagonal => return true,
-                    _ => break, 
-                }
-            }
+        let king_path = BETWEEN[king][king_to] | square_bb(king_to);
+        if bitboard::squares(king_path).any(|square| board.attackers(square, them, others) != 0) {
+            continue;
         }
+        moves.push(movement);
     }
-    
-    false
 }
 
 
-pub fn filter(board: &Board, moves: Vec<Move>) -> Vec<Move> {
-
-    let mut valid_moves = Vec::with_capacity(moves.len());
-    for movement in moves {
-        if is_valid_move(board, movement) {
-            valid_moves.push(movement);
-        }
-    }
-    
-    valid_moves
+// whether the side not to move attacks the square
+pub fn is_square_attacked(board: &Board, square: u16) -> bool {
+    board.attackers(square as usize, board.side_to_move.opposite(), board.occupied()) != 0
 }
 
 
+// the slow way, for checking a single move from outside: play it and see if the king is attacked afterwards
 pub fn is_valid_move(board: &Board, movement: Move) -> bool {
-    let mut new_board:Board = *board;
-
-    let old_king_position = match board.side_to_move {
-        Color::White => board.white_king,
-        Color::Black => board.black_king,
-    };
-
+    // castling has its own rules about the squares in between, the generator knows them
     if movement.is_castle() {
-        if movement.is_castle_kingside() &&
-        !is_square_attacked(board, old_king_position) && 
-        !is_square_attacked(board, old_king_position + 1) &&
-        board.squares[(old_king_position + 1) as usize].is_none() &&
-        !is_square_attacked(board, old_king_position + 2) &&
-        board.squares[(old_king_position + 2) as usize].is_none() {
-            return true;
-        }
-            
-        if movement.is_castle_queenside() && 
-        !is_square_attacked(board, old_king_position) && 
-        !is_square_attacked(board, old_king_position - 1) &&
-        board.squares[(old_king_position - 1) as usize].is_none() &&
-        !is_square_attacked(board, old_king_position - 2) &&
-        board.squares[(old_king_position - 2) as usize].is_none() &&
-        !is_square_attacked(board, old_king_position - 3) && 
-        board.squares[(old_king_position - 3) as usize].is_none() {
-            return true;
-        }
-    
-        return false;
+        return generate_moves(board).contains(&movement);
     }
 
-    let undo_info = new_board.make_move(movement);
-    
-    // Check if the king is attacked after the move
-    let king_is_safe = !new_board.is_check();
-    
-    // Unmake the move
-    new_board.unmake_move(movement, undo_info);
-    if king_is_safe {
-        return true;
-    }
-    return false;
+    let mut new_board:Board = *board;
+    new_board.make_move(movement);
+
+    // the other side is to move now, the king that matters is the one of the side that moved
+    let king = new_board.king_square(board.side_to_move);
+    new_board.attackers(king, new_board.side_to_move, new_board.occupied()) == 0
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+use std::cmp::Reverse;
+
+use crate::bitboard::color_index;
+use crate::board::{Board, Color, Piece};
+use crate::eval;
+use crate::movegen::Move;
+use crate::search::MAX_PLY;
+
+const HASH_MOVE_SCORE: i32 = 1_000_000;
+const CAPTURE_SCORE: i32 = 100_000;
+const PROMOTION_SCORE: i32 = 90_000;
+const FIRST_KILLER_SCORE: i32 = 80_000;
+const SECOND_KILLER_SCORE: i32 = 79_000;
+// history scores are halved when one gets here, so they always stay below the killers
+const HISTORY_LIMIT: i32 = 50_000;
+
+/* Alpha-beta only prunes well if the best move is tried first: a move that makes the opponent's
+position fail high right away saves searching all the others. Moves are tried in this order:
+1- the hash move, the best move the transposition table remembers for the position
+2- captures, most valuable victim first and then least valuable attacker (MVV-LVA), so
+   pawn takes queen goes before queen takes pawn
+3- promotions
+4- killer moves: quiet moves that caused a cutoff at the same ply in another branch,
+   the position is usually similar enough for them to work again
+5- the rest of the quiet moves, by how often they caused cutoffs anywhere in the tree
+   (the history heuristic, a "butterfly" table indexed by from and to square)
+*/
+pub struct MoveOrdering {
+    killers: [[Option<Move>; 2]; MAX_PLY as usize],
+    history: [[[i32; 64]; 64]; 2],
+}
+
+impl Default for MoveOrdering {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MoveOrdering {
+    pub fn new() -> Self {
+        MoveOrdering {
+            killers: [[None; 2]; MAX_PLY as usize],
+            history: [[[0; 64]; 64]; 2],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn order_moves(&self, board: &Board, moves: &mut [Move], hash_move: Option<Move>, ply: i32) {
+        // stable sort, moves with the same score keep the generation order
+        moves.sort_by_cached_key(|&movement| Reverse(self.score_move(board, movement, hash_move, ply)));
+    }
+
+    pub fn score_move(&self, board: &Board, movement: Move, hash_move: Option<Move>, ply: i32) -> i32 {
+        if Some(movement) == hash_move {
+            return HASH_MOVE_SCORE;
+        }
+        if movement.is_capture() {
+            return CAPTURE_SCORE + mvv_lva(board, movement);
+        }
+        if movement.is_promotion() {
+            // queen promotions first, underpromotions are almost never the best move
+            return PROMOTION_SCORE - movement.promotion_piece().unwrap_or(0) as i32;
+        }
+
+        let killers = &self.killers[ply as usize];
+        if killers[0] == Some(movement) {
+            FIRST_KILLER_SCORE
+        } else if killers[1] == Some(movement) {
+            SECOND_KILLER_SCORE
+        } else {
+            self.history[color_index(board.side_to_move)][movement.get_from() as usize][movement.get_to() as usize]
+        }
+    }
+
+    // called when a quiet move fails high
+    pub fn update(&mut self, side: Color, movement: Move, depth: u8, ply: i32) {
+        if movement.is_capture() || movement.is_promotion() {
+            return;
+        }
+
+        let killers = &mut self.killers[ply as usize];
+        if killers[0] != Some(movement) {
+            killers[1] = killers[0];
+            killers[0] = Some(movement);
+        }
+
+        // deeper cutoffs are worth more, they save searching a bigger subtree
+        let entry = &mut self.history[color_index(side)][movement.get_from() as usize][movement.get_to() as usize];
+        *entry += depth as i32 * depth as i32;
+        if *entry >= HISTORY_LIMIT {
+            self.age_history();
+        }
+    }
+
+    fn age_history(&mut self) {
+        for score in self.history.iter_mut().flatten().flatten() {
+            *score /= 2;
+        }
+    }
+}
+
+// most valuable victim, least valuable attacker
+pub fn mvv_lva(board: &Board, movement: Move) -> i32 {
+    // en passant is the only capture with nothing on the destination square
+    let victim = board.squares[movement.get_to() as usize].unwrap_or(Piece::Pawn(Color::White));
+    let attacker = board.squares[movement.get_from() as usize].unwrap_or(Piece::Pawn(Color::White));
+    let attacker_value = match attacker {
+        // piece_value gives the king 0, but it is the last piece we want to capture with
+        Piece::King(_) => 1000,
+        _ => eval::piece_value(&attacker),
+    };
+    eval::piece_value(&victim) * 10 - attacker_value / 10
+}
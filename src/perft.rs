@@ -0,0 +1,49 @@
+use crate::board::Board;
+use crate::movegen::{generate_moves, Move};
+
+/* Perft counts every leaf of the legal move tree to a fixed depth. The numbers for a lot of
+positions are known (https://www.chessprogramming.org/Perft_Results), so a wrong count means
+the move generator or make/unmake has a bug somewhere.
+Divide gives the count under every root move, comparing it against another engine's divide
+tells which move leads to the bug, then you play it and divide again one ply lower.
+*/
+pub fn perft(board: &Board, depth: u8) -> u64 {
+    let mut board = *board;
+    perft_recursive(&mut board, depth)
+}
+
+fn perft_recursive(board: &mut Board, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = generate_moves(board);
+    // the moves are already legal, no need to play the last ply to count it
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for movement in moves {
+        let undo_info = board.make_move(movement);
+        nodes += perft_recursive(board, depth - 1);
+        board.unmake_move(movement, undo_info);
+    }
+    nodes
+}
+
+// the perft count under every root move, in generation order
+pub fn divide(board: &Board, depth: u8) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+
+    let mut board = *board;
+    let mut result = Vec::new();
+    for movement in generate_moves(&board) {
+        let undo_info = board.make_move(movement);
+        result.push((movement, perft_recursive(&mut board, depth - 1)));
+        board.unmake_move(movement, undo_info);
+    }
+    result
+}
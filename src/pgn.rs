@@ -0,0 +1,751 @@
+use std::io::BufRead;
+use std::time::Duration;
+
+use crate::board::{Board, Color};
+use crate::game::Game;
+use crate::movegen::Move;
+use crate::status::GameStatus;
+
+/* PGN, the format games are saved and shared in. A game is a list of tag pairs ([White "..."])
+followed by the moves in SAN, with move numbers, comments in {} or after ;, NAGs ($1 for a good
+move, !, ?!...), variations in () that can nest, and the result at the end.
+
+A game read from PGN is a tree: every node is a move, its first child is the move played next
+and the other children are the variations that could have been played instead. Node 0 is the
+starting position, it has no move. A game played here is the same tree with one child per node.
+
+PgnReader reads games one at a time from anything that implements BufRead, so a big file never
+has to fit in memory. A game with a bad tag, an illegal move or a broken comment gives an error
+with its line and column and the reader goes on with the next game.
+*/
+pub const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+const LINE_WIDTH: usize = 80;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>, // in the order they're written
+    pub start: Board,
+    pub nodes: Vec<PgnNode>,
+    pub result: String, // 1-0, 0-1, 1/2-1/2 or * for a game that isn't over
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct PgnNode {
+    pub movement: Option<Move>, // None only for the root
+    pub parent: usize,
+    pub children: Vec<usize>, // the main line first, then the variations
+    pub comments_before: Vec<String>, // a comment opening a variation, before its first move
+    pub comments: Vec<String>, // after the move
+    pub nags: Vec<u8>,
+    pub clock: Option<Duration>, // [%clk 0:03:25] in a comment, the time left after the move
+    pub eval: Option<PgnEval>, // [%eval 0.35] or [%eval #-3]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PgnEval {
+    Centipawns(i32), // from white's point of view
+    Mate(i32), // moves to mate, negative when black mates
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnError {
+    pub line: usize, // both start at 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+impl From<PgnError> for String {
+    fn from(error: PgnError) -> Self {
+        error.to_string()
+    }
+}
+
+impl Default for PgnGame {
+    fn default() -> Self {
+        Self::new(Board::default())
+    }
+}
+
+impl PgnGame {
+    pub fn new(start: Board) -> Self {
+        PgnGame {
+            tags: Vec::new(),
+            start,
+            nodes: vec![PgnNode::default()],
+            result: "*".to_string(),
+        }
+    }
+
+    // the moves played in a game, with the result if it's over
+    pub fn from_game(game: &Game) -> Self {
+        let mut pgn = Self::new(*game.start_position());
+        let mut node = 0;
+        for &movement in game.moves() {
+            node = pgn.add_move(node, movement);
+        }
+        if let GameStatus::Over(outcome) = game.status() {
+            pgn.result = outcome.result_string().to_string();
+        }
+        pgn
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    // adds the move after the node, as its main line if it's the first one, returns the new node
+    pub fn add_move(&mut self, parent: usize, movement: Move) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(PgnNode { movement: Some(movement), parent, ..PgnNode::default() });
+        self.nodes[parent].children.push(index);
+        index
+    }
+
+    // the nodes of the main line, without the root
+    pub fn mainline(&self) -> Vec<usize> {
+        let mut line = Vec::new();
+        let mut node = 0;
+        while let Some(&next) = self.nodes[node].children.first() {
+            line.push(next);
+            node = next;
+        }
+        line
+    }
+
+    pub fn mainline_moves(&self) -> Vec<Move> {
+        self.mainline().iter().filter_map(|&node| self.nodes[node].movement).collect()
+    }
+
+    // the main line replayed, the moves were checked when they were read
+    pub fn to_game(&self) -> Game {
+        let mut game = Game::new(self.start);
+        for movement in self.mainline_moves() {
+            game.push(movement);
+        }
+        game
+    }
+
+    /* The Seven Tag Roster comes first in its own order, "?" for the ones that are missing (the
+    result always matches the movetext), then SetUp and FEN when the game doesn't start from the
+    normal position, then any other tag.
+    */
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        for name in SEVEN_TAG_ROSTER {
+            let value = match name {
+                "Result" => self.result.as_str(),
+                "Date" => self.tag(name).unwrap_or("????.??.??"),
+                _ => self.tag(name).unwrap_or("?"),
+            };
+            pgn.push_str(&format_tag(name, value));
+        }
+        let standard_start = self.start.squares == Board::default().squares && !self.start.chess960 &&
+            self.start.side_to_move == Board::default().side_to_move &&
+            self.start.castling_rights == Board::default().castling_rights && self.start.en_passant_square.is_none();
+        if !standard_start {
+            pgn.push_str(&format_tag("SetUp", "1"));
+            pgn.push_str(&format_tag("FEN", &self.start.to_fen()));
+            if self.start.chess960 && self.tag("Variant").is_none() {
+                pgn.push_str(&format_tag("Variant", "Chess960"));
+            }
+        }
+        for (name, value) in &self.tags {
+            if !SEVEN_TAG_ROSTER.contains(&name.as_str()) && name != "SetUp" && name != "FEN" {
+                pgn.push_str(&format_tag(name, value));
+            }
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        push_comments(&mut tokens, &self.nodes[0].comments, None, None);
+        // the move numbers count from the start position, some FENs say 0
+        let mut board = self.start;
+        board.fullmove_number = board.fullmove_number.max(1);
+        self.write_line(0, board, true, &mut tokens);
+        tokens.push(self.result.clone());
+
+        // wrapped at 80 columns, breaking only between tokens
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push_str("\n\n");
+        pgn
+    }
+
+    // the line after the node: its main line, with the variations of every move in between
+    fn write_line(&self, mut node: usize, mut board: Board, mut needs_number: bool, tokens: &mut Vec<String>) {
+        while let Some(&main) = self.nodes[node].children.first() {
+            needs_number = self.write_move(main, &board, needs_number, tokens);
+            for &variation in &self.nodes[node].children[1..] {
+                let first = tokens.len();
+                let needs_number = self.write_move(variation, &board, true, tokens);
+                let mut after = board;
+                after.make_move(self.nodes[variation].movement.unwrap());
+                self.write_line(variation, after, needs_number, tokens);
+                // the parentheses stick to the first and last tokens
+                tokens[first].insert(0, '(');
+                tokens.last_mut().unwrap().push(')');
+            }
+            if self.nodes[node].children.len() > 1 {
+                needs_number = true;
+            }
+            board.make_move(self.nodes[main].movement.unwrap());
+            node = main;
+        }
+    }
+
+    // returns whether the next move needs its number written, black moves only get one after an interruption
+    fn write_move(&self, index: usize, board: &Board, needs_number: bool, tokens: &mut Vec<String>) -> bool {
+        let node = &self.nodes[index];
+        push_comments(tokens, &node.comments_before, None, None);
+        let movement = node.movement.unwrap();
+
+        let mut text = match board.side_to_move {
+            Color::White => format!("{}. ", board.fullmove_number),
+            Color::Black if needs_number || !node.comments_before.is_empty() => format!("{}... ", board.fullmove_number),
+            Color::Black => String::new(),
+        };
+        text.push_str(&movement.to_san(board));
+        // "1. e4" stays one token so a line never ends right after a move number
+        tokens.push(text);
+        for nag in &node.nags {
+            tokens.push(format!("${}", nag));
+        }
+        push_comments(tokens, &node.comments, node.clock, node.eval);
+        !node.comments.is_empty() || node.clock.is_some() || node.eval.is_some()
+    }
+}
+
+fn format_tag(name: &str, value: &str) -> String {
+    format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// one comment as a list of words so it can be wrapped like the moves
+fn push_comments(tokens: &mut Vec<String>, comments: &[String], clock: Option<Duration>, eval: Option<PgnEval>) {
+    let mut texts: Vec<String> = comments.to_vec();
+    let mut commands = Vec::new();
+    if let Some(eval) = eval {
+        commands.push(match eval {
+            PgnEval::Centipawns(centipawns) => format!("[%eval {:.2}]", centipawns as f64 / 100.0),
+            PgnEval::Mate(moves) => format!("[%eval #{}]", moves),
+        });
+    }
+    if let Some(clock) = clock {
+        let seconds = clock.as_secs();
+        commands.push(format!("[%clk {}:{:02}:{:02}]", seconds / 3600, seconds / 60 % 60, seconds % 60));
+    }
+    // the commands go in the same comment as the text after the move
+    if !commands.is_empty() {
+        match texts.first_mut() {
+            Some(text) => *text = format!("{} {}", commands.join(" "), text),
+            None => texts.push(commands.join(" ")),
+        }
+    }
+
+    for text in texts {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty() {
+            tokens.push("{}".to_string());
+            continue;
+        }
+        for (i, word) in words.iter().enumerate() {
+            let mut token = word.to_string();
+            if i == 0 {
+                token.insert(0, '{');
+            }
+            if i == words.len() - 1 {
+                token.push('}');
+            }
+            tokens.push(token);
+        }
+    }
+}
+
+pub struct PgnReader<R: BufRead> {
+    reader: R,
+    line_number: usize, // of the last line read
+    pending: Option<String>, // the first line of the next game, already read
+    done: bool,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        PgnReader { reader, line_number: 0, pending: None, done: false }
+    }
+
+    fn read_line(&mut self) -> Option<Result<String, PgnError>> {
+        if let Some(line) = self.pending.take() {
+            self.line_number += 1;
+            return Some(Ok(line));
+        }
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                self.line_number += 1;
+                Some(Ok(line.trim_end_matches(['\n', '\r']).to_string()))
+            },
+            Err(e) => Some(Err(PgnError { line: self.line_number + 1, column: 1, message: e.to_string() })),
+        }
+    }
+
+    // the lines of the next game and the number of its first line, a game ends where the tags of the next one start
+    fn next_game_text(&mut self) -> Option<Result<(usize, String), PgnError>> {
+        let mut text = String::new();
+        let mut first_line = 0;
+        let mut in_movetext = false;
+        let mut in_comment = false;
+        loop {
+            let line = match self.read_line() {
+                None => break,
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(line)) => line,
+            };
+            let trimmed = line.trim_start();
+            if text.is_empty() && trimmed.is_empty() {
+                continue;
+            }
+            if in_movetext && !in_comment && trimmed.starts_with('[') {
+                self.pending = Some(line);
+                self.line_number -= 1;
+                break;
+            }
+            if first_line == 0 {
+                first_line = self.line_number;
+            }
+            if !in_comment && !trimmed.is_empty() && !trimmed.starts_with('[') && !trimmed.starts_with('%') {
+                in_movetext = true;
+            }
+            // braces can span lines, a ; comment ends with its line
+            for ch in line.chars() {
+                match ch {
+                    '{' if !in_comment => in_comment = true,
+                    '}' if in_comment => in_comment = false,
+                    ';' if !in_comment => break,
+                    _ => {}
+                }
+            }
+            text.push_str(&line);
+            text.push('\n');
+        }
+        if text.is_empty() {
+            return None;
+        }
+        Some(Ok((first_line, text)))
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_game_text()? {
+            Ok((first_line, text)) => Some(parse_game(&text, first_line)),
+            Err(e) => {
+                // after an I/O error there's nothing more to read
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+// every game of a PGN text, the bad ones left out with their errors
+pub fn read_all(pgn: &str) -> (Vec<PgnGame>, Vec<PgnError>) {
+    let mut games = Vec::new();
+    let mut errors = Vec::new();
+    for game in PgnReader::new(pgn.as_bytes()) {
+        match game {
+            Ok(game) => games.push(game),
+            Err(e) => errors.push(e),
+        }
+    }
+    (games, errors)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Open,
+    Close,
+    Nag(u8),
+    Result(String),
+    Move(String),
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer {
+    fn new(text: &str, first_line: usize) -> Self {
+        Lexer { chars: text.chars().collect(), index: 0, line: first_line, column: 1 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.index += 1;
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+
+    fn error(&self, line: usize, column: usize, message: String) -> PgnError {
+        PgnError { line, column, message }
+    }
+
+    // the next token with the line and column where it starts
+    fn next_token(&mut self) -> Result<Option<(Token, usize, usize)>, PgnError> {
+        loop {
+            let (line, column) = (self.line, self.column);
+            let Some(ch) = self.bump() else { return Ok(None) };
+            let token = match ch {
+                _ if ch.is_whitespace() => continue,
+                // escape lines, for other programs
+                '%' if column == 1 => {
+                    while self.peek().is_some_and(|ch| ch != '\n') {
+                        self.bump();
+                    }
+                    continue;
+                },
+                ';' => {
+                    let mut comment = String::new();
+                    while let Some(ch) = self.peek() && ch != '\n' {
+                        comment.push(ch);
+                        self.bump();
+                    }
+                    Token::Comment(comment.trim().to_string())
+                },
+                '{' => {
+                    let mut comment = String::new();
+                    loop {
+                        match self.bump() {
+                            Some('}') => break,
+                            Some(ch) => comment.push(ch),
+                            None => return Err(self.error(line, column, "comment never closed".to_string())),
+                        }
+                    }
+                    Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" "))
+                },
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '[' => self.tag(line, column)?,
+                '$' => {
+                    let mut number = String::new();
+                    while let Some(ch) = self.peek() && ch.is_ascii_digit() {
+                        number.push(ch);
+                        self.bump();
+                    }
+                    Token::Nag(number.parse().map_err(|_| self.error(line, column, format!("invalid NAG ${}", number)))?)
+                },
+                _ => {
+                    let mut symbol = ch.to_string();
+                    while let Some(ch) = self.peek() && !ch.is_whitespace() && !"{}();[]$".contains(ch) {
+                        symbol.push(ch);
+                        self.bump();
+                    }
+                    match self.symbol(symbol, line, column)? {
+                        Some(token) => token,
+                        None => continue,
+                    }
+                },
+            };
+            return Ok(Some((token, line, column)));
+        }
+    }
+
+    // [Name "value"], the [ is already read
+    fn tag(&mut self, line: usize, column: usize) -> Result<Token, PgnError> {
+        let mut name = String::new();
+        while let Some(ch) = self.peek() && (ch.is_alphanumeric() || ch == '_') {
+            name.push(ch);
+            self.bump();
+        }
+        while self.peek().is_some_and(|ch| ch == ' ' || ch == '\t') {
+            self.bump();
+        }
+        if name.is_empty() || self.bump() != Some('"') {
+            return Err(self.error(line, column, "invalid tag".to_string()));
+        }
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('\\') => match self.bump() {
+                    Some(ch) => value.push(ch),
+                    None => return Err(self.error(line, column, "tag never closed".to_string())),
+                },
+                Some('"') => break,
+                Some('\n') | None => return Err(self.error(line, column, "tag value never closed".to_string())),
+                Some(ch) => value.push(ch),
+            }
+        }
+        while self.peek().is_some_and(|ch| ch == ' ' || ch == '\t') {
+            self.bump();
+        }
+        if self.bump() != Some(']') {
+            return Err(self.error(line, column, format!("tag {} not closed with ]", name)));
+        }
+        Ok(Token::Tag(name, value))
+    }
+
+    // results, move numbers (skipped), and moves with their ! and ? turned into NAGs
+    fn symbol(&self, symbol: String, line: usize, column: usize) -> Result<Option<Token>, PgnError> {
+        if ["1-0", "0-1", "1/2-1/2", "*"].contains(&symbol.as_str()) {
+            return Ok(Some(Token::Result(symbol)));
+        }
+        // 12. or 12... or 12.e4 written together
+        let without_number = symbol.trim_start_matches(|ch: char| ch.is_ascii_digit());
+        let symbol = if without_number.len() < symbol.len() && without_number.starts_with('.') {
+            without_number.trim_start_matches('.')
+        } else {
+            symbol.as_str()
+        };
+        if symbol.is_empty() {
+            return Ok(None);
+        }
+        if !symbol.starts_with(|ch: char| ch.is_ascii_alphanumeric() || ch == '!' || ch == '?') {
+            return Err(self.error(line, column, format!("unexpected {}", symbol)));
+        }
+        Ok(Some(Token::Move(symbol.to_string())))
+    }
+}
+
+// the traditional suffixes and their NAG numbers
+const SUFFIX_NAGS: [(&str, u8); 6] = [("!!", 3), ("??", 4), ("!?", 5), ("?!", 6), ("!", 1), ("?", 2)];
+
+fn parse_game(text: &str, first_line: usize) -> Result<PgnGame, PgnError> {
+    let mut lexer = Lexer::new(text, first_line);
+    let mut game = PgnGame::default();
+    let mut tags_done = false;
+
+    // where the moves go: the last node, the position after it, and whether it starts a variation
+    let mut node = 0;
+    let mut board = game.start;
+    let mut variation_start = false;
+    let mut pending_comments: Vec<String> = Vec::new();
+    // the position and node to go back to at the end of every open variation, and where it opened
+    let mut stack: Vec<(usize, Board, usize, usize)> = Vec::new();
+    let mut result = None;
+    // a bad FEN tag is reported where the tag is
+    let mut fen_at = None;
+
+    while let Some((token, line, column)) = lexer.next_token()? {
+        if result.is_some() {
+            return Err(lexer.error(line, column, "moves after the result".to_string()));
+        }
+        match token {
+            Token::Tag(name, value) => {
+                if tags_done {
+                    return Err(lexer.error(line, column, format!("tag {} in the middle of the moves", name)));
+                }
+                if name == "FEN" {
+                    fen_at = Some((line, column));
+                }
+                game.tags.push((name, value));
+            },
+            other => {
+                if !tags_done {
+                    tags_done = true;
+                    let (line, column) = fen_at.unwrap_or((line, column));
+                    game.start = start_position(&game.tags).map_err(|message| lexer.error(line, column, message))?;
+                    board = game.start;
+                }
+                match other {
+                    Token::Tag(..) => unreachable!(),
+                    Token::Comment(text) => {
+                        let (text, clock, eval) = parse_commands(&text);
+                        let target = &mut game.nodes[node];
+                        if clock.is_some() {
+                            target.clock = clock;
+                        }
+                        if eval.is_some() {
+                            target.eval = eval;
+                        }
+                        if !text.is_empty() {
+                            if variation_start {
+                                pending_comments.push(text);
+                            } else {
+                                target.comments.push(text);
+                            }
+                        }
+                    },
+                    Token::Nag(nag) => {
+                        if node == 0 || variation_start {
+                            return Err(lexer.error(line, column, format!("${} before any move", nag)));
+                        }
+                        game.nodes[node].nags.push(nag);
+                    },
+                    Token::Open => {
+                        // the variation replaces the last move, it starts from the position before it
+                        if node == 0 || variation_start {
+                            return Err(lexer.error(line, column, "variation before any move".to_string()));
+                        }
+                        stack.push((node, board, line, column));
+                        node = game.nodes[node].parent;
+                        board = position_after(&game, node);
+                        variation_start = true;
+                    },
+                    Token::Close => {
+                        let Some((saved_node, saved_board, _, _)) = stack.pop() else {
+                            return Err(lexer.error(line, column, "unmatched )".to_string()));
+                        };
+                        game.nodes[node].comments.append(&mut pending_comments);
+                        node = saved_node;
+                        board = saved_board;
+                        variation_start = false;
+                    },
+                    Token::Result(token) => {
+                        if !stack.is_empty() {
+                            return Err(lexer.error(line, column, "result inside a variation".to_string()));
+                        }
+                        result = Some(token);
+                    },
+                    Token::Move(symbol) => {
+                        // moves can have their ! and ? glued on
+                        let mut san = symbol.as_str();
+                        let mut nags = Vec::new();
+                        while let Some((suffix, nag)) = SUFFIX_NAGS.iter().find(|(suffix, _)| san.ends_with(suffix)) {
+                            san = &san[..san.len() - suffix.len()];
+                            nags.insert(0, *nag);
+                        }
+                        if san.is_empty() {
+                            if node == 0 || variation_start {
+                                return Err(lexer.error(line, column, format!("{} before any move", symbol)));
+                            }
+                            game.nodes[node].nags.extend(nags);
+                            continue;
+                        }
+                        let movement = Move::from_san(san, &board).map_err(|message| lexer.error(line, column, message))?;
+                        let parent = node;
+                        node = game.add_move(parent, movement);
+                        game.nodes[node].nags = nags;
+                        game.nodes[node].comments_before.append(&mut pending_comments);
+                        board.make_move(movement);
+                        variation_start = false;
+                    },
+                }
+            },
+        }
+    }
+
+    if let Some(&(_, _, line, column)) = stack.last() {
+        return Err(lexer.error(line, column, "variation never closed".to_string()));
+    }
+    if !tags_done {
+        let (line, column) = fen_at.unwrap_or((lexer.line, lexer.column));
+        game.start = start_position(&game.tags).map_err(|message| lexer.error(line, column, message))?;
+    }
+    game.result = result
+        .or_else(|| game.tag("Result").map(|result| result.to_string()))
+        .unwrap_or_else(|| "*".to_string());
+    Ok(game)
+}
+
+// the FEN tag if there's one, Chess960 games can say so in the Variant tag
+fn start_position(tags: &[(String, String)]) -> Result<Board, String> {
+    let tag = |name: &str| tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str());
+    let mut board = match tag("FEN") {
+        Some(fen) => Board::from_fen(fen).map_err(|e| format!("invalid FEN tag: {}", e))?,
+        None => Board::default(),
+    };
+    if tag("Variant").is_some_and(|variant| variant.to_lowercase().contains("960") || variant.eq_ignore_ascii_case("fischerandom")) {
+        board.chess960 = true;
+    }
+    Ok(board)
+}
+
+// replays the moves from the start to the node
+fn position_after(game: &PgnGame, node: usize) -> Board {
+    let mut moves = Vec::new();
+    let mut current = node;
+    while current != 0 {
+        moves.push(game.nodes[current].movement.unwrap());
+        current = game.nodes[current].parent;
+    }
+    let mut board = game.start;
+    for movement in moves.into_iter().rev() {
+        board.make_move(movement);
+    }
+    board
+}
+
+// takes the [%clk ...] and [%eval ...] commands out of a comment
+fn parse_commands(comment: &str) -> (String, Option<Duration>, Option<PgnEval>) {
+    let mut text = String::new();
+    let mut clock = None;
+    let mut eval = None;
+    let mut rest = comment;
+    while let Some(start) = rest.find("[%") {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find(']') else { break };
+        let command = &rest[start + 2..start + end];
+        let mut parts = command.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("clk"), Some(value)) => clock = parse_clock(value),
+            (Some("eval"), Some(value)) => eval = parse_eval(value),
+            _ => text.push_str(&rest[start..start + end + 1]), // some other program's command, kept
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    (text.split_whitespace().collect::<Vec<_>>().join(" "), clock, eval)
+}
+
+// h:mm:ss with optional fractions of a second. f64 parses "inf" and "nan" too, those and
+// negative times are dropped like any other broken clock
+fn parse_clock(value: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    for part in value.split(':') {
+        let part = part.parse::<f64>().ok().filter(|part| part.is_finite() && *part >= 0.0)?;
+        seconds = seconds * 60.0 + part;
+    }
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+fn parse_eval(value: &str) -> Option<PgnEval> {
+    match value.strip_prefix('#') {
+        Some(mate) => mate.parse().ok().map(PgnEval::Mate),
+        None => value.parse::<f64>().ok().map(|pawns| PgnEval::Centipawns((pawns * 100.0).round() as i32)),
+    }
+}
@@ -0,0 +1,225 @@
+use crate::board::{Board, Piece};
+use crate::movegen::{generate_moves, Move};
+
+/* Standard Algebraic Notation, what people and PGN files write: Nf3, exd5, O-O, e8=Q+.
+A SAN move only makes sense in its position, so parsing goes through the legal moves and keeps
+the ones that fit what was written. Nothing left means the move is illegal (or a typo), more
+than one means the piece needed disambiguation (Nbd2 instead of Nd2).
+Check and mate suffixes and annotations (+ # ! ?) are accepted and ignored, so is 0-0 for O-O,
+a promotion without the = (e8Q) and long algebraic notation (Ng1-f3, e7xd8=Q).
+
+Writing goes the other way: the letter of the piece (none for pawns), as little of the origin
+square as it takes to tell it from the other pieces of the same kind that can go to the same
+square (the file if that's enough, else the rank, else both), x for captures, the promotion and
++ or # at the end, which needs the move played to know.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Notation {
+    San, // Nf3, exd5, O-O, e8=Q+
+    Lan, // Ng1-f3, e4xd5, O-O, e7-e8=Q+
+    Uci, // g1f3, e4d5, e1g1, e7e8q
+}
+
+impl Move {
+    pub fn to_notation(&self, board: &Board, notation: Notation) -> String {
+        match notation {
+            Notation::San => self.to_san(board),
+            Notation::Lan => self.to_lan(board),
+            Notation::Uci => self.to_uci_for(board),
+        }
+    }
+
+    pub fn to_san(&self, board: &Board) -> String {
+        let mut san = String::new();
+        if self.is_castle() {
+            san.push_str(if self.is_castle_kingside() { "O-O" } else { "O-O-O" });
+        } else {
+            let piece = board.squares[self.get_from() as usize];
+            if let Some(letter) = piece.and_then(|piece| piece_letter(&piece)) {
+                san.push(letter);
+                san.push_str(&self.disambiguation(board));
+            } else if self.is_capture() {
+                // pawn captures always say which file they come from
+                san.push(Board::square_to_string(self.get_from() as u8).chars().next().unwrap());
+            }
+            if self.is_capture() {
+                san.push('x');
+            }
+            san.push_str(&Board::square_to_string(self.get_to() as u8));
+            self.push_promotion(&mut san);
+        }
+        self.push_check(board, &mut san);
+        san
+    }
+
+    // long algebraic notation, the full origin square every time
+    pub fn to_lan(&self, board: &Board) -> String {
+        let mut lan = String::new();
+        if self.is_castle() {
+            lan.push_str(if self.is_castle_kingside() { "O-O" } else { "O-O-O" });
+        } else {
+            if let Some(letter) = board.squares[self.get_from() as usize].and_then(|piece| piece_letter(&piece)) {
+                lan.push(letter);
+            }
+            lan.push_str(&Board::square_to_string(self.get_from() as u8));
+            lan.push(if self.is_capture() { 'x' } else { '-' });
+            lan.push_str(&Board::square_to_string(self.get_to() as u8));
+            self.push_promotion(&mut lan);
+        }
+        self.push_check(board, &mut lan);
+        lan
+    }
+
+    // what has to be added after the piece letter so no other piece of the same kind fits
+    pub fn disambiguation(&self, board: &Board) -> String {
+        let from = self.get_from();
+        let moving_piece = board.squares[from as usize];
+
+        let others: Vec<u16> = generate_moves(board).iter()
+            .filter(|movement| movement.get_to() == self.get_to() && movement.get_from() != from && !movement.is_castle())
+            .filter(|movement| board.squares[movement.get_from() as usize] == moving_piece)
+            .map(|movement| movement.get_from())
+            .collect();
+
+        let square = Board::square_to_string(from as u8);
+        if others.is_empty() {
+            String::new()
+        } else if others.iter().all(|other| other % 8 != from % 8) {
+            square[..1].to_string()
+        } else if others.iter().all(|other| other / 8 != from / 8) {
+            square[1..].to_string()
+        } else {
+            square
+        }
+    }
+
+    fn push_promotion(&self, notation: &mut String) {
+        if let Some(promotion) = self.promotion_piece() {
+            notation.push('=');
+            notation.push(['Q', 'R', 'B', 'N'][promotion as usize]);
+        }
+    }
+
+    fn push_check(&self, board: &Board, notation: &mut String) {
+        let mut after = *board;
+        after.make_move(*self);
+        if after.is_check() {
+            notation.push(if generate_moves(&after).is_empty() { '#' } else { '+' });
+        }
+    }
+
+    pub fn from_san(input: &str, board: &Board) -> Result<Move, String> {
+        let san = input.trim().trim_end_matches(['+', '#', '!', '?']);
+        let san = san.strip_suffix("e.p.").unwrap_or(san).trim_end();
+        if san.is_empty() {
+            return Err("empty move".to_string());
+        }
+        let legal_moves = generate_moves(board);
+
+        let castle = match san {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(kingside) = castle {
+            return legal_moves.into_iter()
+                .find(|movement| if kingside { movement.is_castle_kingside() } else { movement.is_castle_queenside() })
+                .ok_or_else(|| format!("{} is not legal here", input.trim()));
+        }
+
+        // LAN has a dash between the squares, without it it's SAN with the whole origin square
+        let mut chars: Vec<char> = san.chars().filter(|&ch| ch != '-').collect();
+        if chars.is_empty() {
+            return Err(format!("invalid move {}", input.trim()));
+        }
+
+        // the piece letter is always uppercase, a lowercase b is the b file
+        let piece = match chars[0] {
+            'N' => Some(3),
+            'B' => Some(2),
+            'R' => Some(1),
+            'Q' => Some(0),
+            'K' => Some(4),
+            _ => None,
+        };
+        if piece.is_some() {
+            chars.remove(0);
+        }
+
+        // promotion, e8=Q or e8Q, only for pawns
+        let mut promotion = None;
+        if piece.is_none() && let Some(&last) = chars.last() && "QRBN".contains(last) {
+            promotion = Some(match last {
+                'Q' => 0,
+                'R' => 1,
+                'B' => 2,
+                _ => 3,
+            });
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+
+        if chars.len() < 2 {
+            return Err(format!("invalid move {}", input.trim()));
+        }
+        let destination: String = chars[chars.len() - 2..].iter().collect();
+        let to = Board::string_to_square(&destination).map_err(|_| format!("invalid square {} in {}", destination, input.trim()))? as u16;
+        chars.truncate(chars.len() - 2);
+
+        let capture = chars.last() == Some(&'x');
+        if capture {
+            chars.pop();
+        }
+
+        // whatever is left tells where the piece comes from: a file, a rank or both
+        let mut from_file = None;
+        let mut from_rank = None;
+        for ch in chars {
+            match ch {
+                'a'..='h' if from_file.is_none() && from_rank.is_none() => from_file = Some(ch as u16 - 'a' as u16),
+                '1'..='8' if from_rank.is_none() => from_rank = Some(ch as u16 - '1' as u16),
+                _ => return Err(format!("invalid move {}", input.trim())),
+            }
+        }
+        // a pawn without a file moves straight ahead, captures have to say where they come from
+        if piece.is_none() && from_file.is_none() {
+            from_file = Some(to % 8);
+        }
+
+        let candidates: Vec<Move> = legal_moves.into_iter().filter(|movement| {
+            let from = movement.get_from();
+            let moving = board.squares[from as usize];
+            let same_piece = matches!((piece, moving),
+                (None, Some(Piece::Pawn(_))) |
+                (Some(3), Some(Piece::Knight(_))) |
+                (Some(2), Some(Piece::Bishop(_))) |
+                (Some(1), Some(Piece::Rook(_))) |
+                (Some(0), Some(Piece::Queen(_))) |
+                (Some(4), Some(Piece::King(_))));
+            same_piece && !movement.is_castle() && movement.get_to() == to &&
+                from_file.is_none_or(|file| from % 8 == file) &&
+                from_rank.is_none_or(|rank| from / 8 == rank) &&
+                movement.promotion_piece() == promotion
+        }).collect();
+
+        match candidates.as_slice() {
+            [] => Err(format!("{} is not legal here", input.trim())),
+            [movement] if capture && !movement.is_capture() => Err(format!("{} doesn't capture anything", input.trim())),
+            [movement] => Ok(*movement),
+            _ => {
+                let options: Vec<String> = candidates.iter().map(|movement| movement.to_uci()).collect();
+                Err(format!("{} is ambiguous, it could be {}", input.trim(), options.join(" or ")))
+            },
+        }
+    }
+}
+
+// None for pawns, SAN doesn't write a letter for them
+fn piece_letter(piece: &Piece) -> Option<char> {
+    match piece {
+        Piece::Pawn(_) => None,
+        _ => Some(Board::piece_to_char(piece).to_ascii_uppercase()),
+    }
+}
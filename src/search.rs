@@ -1,11 +1,16 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
 use rand::Rng;
 
 use crate::board::Color;
 use crate::eval;
-use crate::{board::Board, movegen::{Move, generate_moves}};
+use crate::ordering::MoveOrdering;
+use crate::tt::{Bound, TranspositionTable};
+use crate::{board::Board, movegen::{Move, generate_captures, generate_moves}};
 
 
-pub fn _get_random_element(vector: &Vec<Move>) -> Option<Move> {
+pub fn _get_random_element(vector: &[Move]) -> Option<Move> {
     if vector.is_empty() {
         return None;
     }
@@ -13,7 +18,7 @@ pub fn _get_random_element(vector: &Vec<Move>) -> Option<Move> {
     let mut rng = rand::rng();
     let index = rng.random_range(0..vector.len());
     
-    Some(vector[index].clone())
+    Some(vector[index])
 }
 
 
@@ -43,86 +48,331 @@ pub fn _minmax_depth_1(board: &Board, moves: &Vec<Move>) -> Option<Move> {
         };
 
         if is_better {
-            println!("New best score ({}) for {}", score, board.side_to_move.to_string());
+            println!("New best score ({}) for {}", score, board.side_to_move);
             best_score = score;
             best_move = move_candidate;
         }
     }
-    return Some(best_move);
+    Some(best_move)
+
+}
+
+pub const MATE_SCORE: i32 = 1_000_000;
+// any score above this is a forced mate, the difference tells how many plies away it is
+pub const MATE_THRESHOLD: i32 = MATE_SCORE - 1000;
+const INFINITY: i32 = MATE_SCORE + 1;
+
+pub const MAX_DEPTH: u8 = 64;
+// the quiescence search can go past the depth, but not forever
+pub const MAX_PLY: i32 = 128;
+// time kept aside for the GUI and the OS between our move and the clock stopping
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
 
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: i32, // from the point of view of the side to move
+    pub nodes: u64,
 }
 
+/* What the search is allowed to spend on a move. With a clock the budget is split in two:
+the soft limit is when we stop starting new iterations (the next one would probably not finish),
+the hard limit is when we abort the iteration in progress no matter what.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchLimits {
+    pub depth: Option<u8>,
+    pub move_time: Option<Duration>,
+    pub time_left: Option<Duration>,
+    pub increment: Duration,
+    pub moves_to_go: Option<u32>,
+    pub infinite: bool,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u8) -> Self {
+        SearchLimits { depth: Some(depth), ..Default::default() }
+    }
+
+    pub fn move_time(move_time: Duration) -> Self {
+        SearchLimits { move_time: Some(move_time), ..Default::default() }
+    }
+
+    // (soft, hard), None when the search is only limited by depth or by a "stop"
+    pub fn time_budget(&self) -> Option<(Duration, Duration)> {
+        if self.infinite {
+            return None;
+        }
+        if let Some(move_time) = self.move_time {
+            let move_time = move_time.saturating_sub(MOVE_OVERHEAD).max(Duration::from_millis(1));
+            return Some((move_time, move_time));
+        }
+
+        let available = self.time_left?.saturating_sub(MOVE_OVERHEAD).max(Duration::from_millis(1));
+        // without a move count assume the game goes on for 30 more moves
+        let moves_to_go = self.moves_to_go.unwrap_or(30).clamp(1, 50);
+        let soft = (available / moves_to_go + self.increment * 3 / 4).min(available);
+        let hard = (soft * 3).min(available);
+        Some((soft, hard))
+    }
+}
+
+// state shared by every node of one search
+struct SearchContext<'a> {
+    stop: &'a AtomicBool,
+    tt: &'a mut TranspositionTable,
+    nodes: u64,
+    deadline: Option<Instant>,
+    timed_out: bool,
+    ordering: MoveOrdering,
+}
+
+impl SearchContext<'_> {
+    fn should_stop(&mut self) -> bool {
+        // looking at the clock on every node would be too slow
+        if !self.timed_out && self.nodes.is_multiple_of(1024)
+            && let Some(deadline) = self.deadline {
+            self.timed_out = Instant::now() >= deadline;
+        }
+        self.timed_out || self.stop.load(Ordering::Relaxed)
+    }
+}
+
+// kept for the callers that just want a move at a fixed depth
 pub fn minimax_best_move(board: &Board, depth: u8) -> Option<Move> {
-    let moves = generate_moves(board);
+    let stop = AtomicBool::new(false);
+    let mut tt = TranspositionTable::default();
+    alpha_beta_search(board, depth, &stop, &mut tt).map(|result| result.best_move)
+}
+
+/* Iterative deepening searches depth 1, then 2, then 3... until the time is up. Every iteration
+costs a lot more than the previous ones together, so repeating them is cheap, and it means we
+always have the move of the last finished depth to play when time runs out. The transposition
+table also carries the best moves of each iteration over to the next one.
+report is called after every finished iteration with its depth, result and the time so far.
+Returns None only if there are no legal moves.
+*/
+pub fn iterative_deepening(board: &Board, limits: &SearchLimits, stop: &AtomicBool, tt: &mut TranspositionTable,
+    mut report: impl FnMut(u8, &SearchResult, Duration)) -> Option<SearchResult> {
+    let start = Instant::now();
+    let budget = limits.time_budget();
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+
+    tt.new_search();
+    let mut context = SearchContext {
+        stop,
+        tt,
+        nodes: 0,
+        deadline: budget.map(|(_, hard)| start + hard),
+        timed_out: false,
+        ordering: MoveOrdering::new(),
+    };
+
+    let mut best: Option<SearchResult> = None;
+    for depth in 1..=max_depth {
+        let Some(result) = search_root(board, depth, &mut context) else { break };
+        report(depth, &result, start.elapsed());
+
+        let found_mate = result.score.abs() > MATE_THRESHOLD;
+        best = Some(result);
+        if found_mate {
+            break;
+        }
+        if let Some((soft, _)) = budget && start.elapsed() >= soft {
+            break;
+        }
+    }
+
+    // if not even depth 1 finished, any legal move beats forfeiting on time
+    best.or_else(|| generate_moves(board).first().map(|&best_move| SearchResult { best_move, score: 0, nodes: context.nodes }))
+}
+
+/* Negamax is minimax written once: the score of a position for one side is minus the score for
+the other one, so instead of a maximizing and a minimizing branch every node maximizes the
+negated scores of its children.
+Alpha-beta keeps a window (alpha, beta) of scores that still matter: alpha is what the side to move
+is already guaranteed somewhere else, beta is what the opponent is guaranteed. As soon as a move
+scores beta or more the opponent will never allow this position, so the rest of the moves are skipped.
+It finds the same move as minimax, just without looking at lines that can't change the result.
+Returns None if there are no legal moves or if the search was stopped before finishing.
+*/
+pub fn alpha_beta_search(board: &Board, depth: u8, stop: &AtomicBool, tt: &mut TranspositionTable) -> Option<SearchResult> {
+    tt.new_search();
+    let mut context = SearchContext { stop, tt, nodes: 0, deadline: None, timed_out: false, ordering: MoveOrdering::new() };
+    search_root(board, depth, &mut context)
+}
+
+fn search_root(board: &Board, depth: u8, context: &mut SearchContext) -> Option<SearchResult> {
+    let mut moves = generate_moves(board);
     if moves.is_empty() {
         return None;
     }
+
+    // the best move of the previous iteration goes first
+    let hash_move = context.tt.probe(board.hash, 0).and_then(|entry| entry.best_move);
+    context.ordering.order_moves(board, &mut moves, hash_move, 0);
     
-    let maximizing = board.side_to_move == Color::White;
+    let depth = depth.max(1);
     let mut best_move = moves[0];
-    let mut best_score = if maximizing { i32::MIN } else { i32::MAX };
+    let mut best_score = -INFINITY;
+    let mut alpha = -INFINITY;
     
     for move_candidate in moves {
         let mut board_copy = *board;
         board_copy.make_move(move_candidate);
-        let score = minimax(&board_copy, depth - 1, !maximizing, board.side_to_move.opposite());
-        
-        let is_better = if maximizing {
-            score > best_score
-        } else {
-            score < best_score
-        };
+        let score = -negamax(&board_copy, depth - 1, 1, -INFINITY, -alpha, context);
+
+        if context.should_stop() {
+            return None;
+        }
         
-        if is_better {
+        if score > best_score {
             best_score = score;
             best_move = move_candidate;
+            alpha = alpha.max(score);
         }
     }
+
+    context.tt.store(board.hash, depth, best_score, Bound::Exact, Some(best_move), 0);
     
-    println!("{}", best_score);
-    Some(best_move)
+    Some(SearchResult { best_move, score: best_score, nodes: context.nodes })
 }
 
-fn minimax(board: &Board, depth: u8, maximizing_player: bool, active_side: Color) -> i32 {
+// fail-soft: the returned score can fall outside of the (alpha, beta) window
+fn negamax(board: &Board, depth: u8, ply: i32, mut alpha: i32, beta: i32, context: &mut SearchContext) -> i32 {
+    context.nodes += 1;
+    if context.should_stop() {
+        return 0;
+    }
+
     if depth == 0 {
-        return eval::eval(&board);
+        return quiescence(board, ply, alpha, beta, context);
     }
 
-    let mut board_copy = *board;
-    board_copy.side_to_move = active_side;
-    let moves = generate_moves(&board_copy);
+    let tt_entry = context.tt.probe(board.hash, ply);
+    if let Some(entry) = tt_entry
+        && entry.depth >= depth {
+        match entry.bound {
+            Bound::Exact => return entry.score,
+            Bound::Lower if entry.score >= beta => return entry.score,
+            Bound::Upper if entry.score <= alpha => return entry.score,
+            _ => {}
+        }
+    }
+
+    let mut moves = generate_moves(board);
 
     if moves.is_empty() {
-        if board_copy.is_check() {
-            return if active_side == Color::White { i32::MIN + depth as i32 } else { i32::MAX - depth as i32 };
+        if board.is_check() {
+            // closer mates score higher so the engine goes for the fastest one
+            return ply - MATE_SCORE;
         } else {
             //stalemate
             return 0;
         }
     }
 
-    let mut best_score = if maximizing_player {
-        i32::MIN
-    } else {
-        i32::MAX
-    };
+    // not deep enough for a cutoff, but its best move is still the best guess
+    let hash_move = tt_entry.and_then(|entry| entry.best_move);
+    context.ordering.order_moves(board, &mut moves, hash_move, ply);
+
+    let original_alpha = alpha;
+    let mut best_score = -INFINITY;
+    let mut best_move = moves[0];
 
     for move_candidate in moves {
-        let mut board_for_move = board_copy;
-        
+        let mut board_for_move = *board;
         board_for_move.make_move(move_candidate);
 
-        let opposite_side = active_side.opposite(); 
-            
-        let eval_score = minimax(&board_for_move, depth - 1, !maximizing_player, opposite_side);
+        let score = -negamax(&board_for_move, depth - 1, ply + 1, -beta, -alpha, context);
 
-        best_score = if maximizing_player {
-            best_score.max(eval_score)
+        if score > best_score {
+            best_score = score;
+            best_move = move_candidate;
+            if score > alpha {
+                alpha = score;
+                if alpha >= beta {
+                    context.ordering.update(board.side_to_move, move_candidate, depth, ply);
+                    break;
+                }
+            }
+        }
+    }
+
+    // a stopped search returns garbage, it must not end up in the table
+    if !context.should_stop() {
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
         } else {
-            best_score.min(eval_score)
+            Bound::Exact
         };
+        context.tt.store(board.hash, depth, best_score, bound, Some(best_move), ply);
+    }
+
+    best_score
+}
+
+/* Stopping the search at a fixed depth and evaluating there is blind to whatever happens next:
+a position where we just took a pawn with the queen looks great, even if the queen gets taken back.
+The quiescence search keeps going through captures and promotions (every move when in check)
+until the position is quiet. The side to move can also "stand pat": not capture anything and keep
+the static eval, since captures are never forced.
+*/
+fn quiescence(board: &Board, ply: i32, mut alpha: i32, beta: i32, context: &mut SearchContext) -> i32 {
+    context.nodes += 1;
+    if context.should_stop() {
+        return 0;
+    }
+    if ply >= MAX_PLY {
+        return relative_eval(board);
+    }
+
+    let in_check = board.is_check();
+    let mut best_score;
+    let mut moves;
+
+    if in_check {
+        // no standing pat in check, every evasion has to be looked at
+        moves = generate_moves(board);
+        if moves.is_empty() {
+            return ply - MATE_SCORE;
+        }
+        best_score = -INFINITY;
+    } else {
+        best_score = relative_eval(board);
+        if best_score >= beta {
+            return best_score;
+        }
+        alpha = alpha.max(best_score);
+        moves = generate_captures(board);
+    }
+    context.ordering.order_moves(board, &mut moves, None, ply);
+
+    for move_candidate in moves {
+        let mut board_for_move = *board;
+        board_for_move.make_move(move_candidate);
+
+        let score = -quiescence(&board_for_move, ply + 1, -beta, -alpha, context);
+
+        if score > best_score {
+            best_score = score;
+            if score > alpha {
+                alpha = score;
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
     }
 
     best_score
+}
 
-}
\ No newline at end of file
+// eval::eval is always from white's point of view, negamax wants it from the side to move
+fn relative_eval(board: &Board) -> i32 {
+    match board.side_to_move {
+        Color::White => eval::eval(board),
+        Color::Black => -eval::eval(board),
+    }
+}
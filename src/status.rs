@@ -0,0 +1,144 @@
+use crate::board::{Board, Color, Piece};
+use crate::movegen::generate_moves;
+
+/* Everything that can end a game on the board, so every front-end (the terminal game, xboard,
+and whatever comes later) agrees on when a game is over and why.
+The 50-move rule and threefold repetition are draws a player can claim, the game goes on until
+someone does, so they're reported as Claimable and the front-end decides whether to claim. The
+75-move rule and fivefold repetition end the game on their own.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameStatus {
+    Ongoing,
+    Claimable(Reason),
+    Over(Outcome),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outcome {
+    pub winner: Option<Color>, // None for draws
+    pub reason: Reason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reason {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    FiftyMoveRule,
+    SeventyFiveMoveRule,
+    ThreefoldRepetition,
+    FivefoldRepetition,
+}
+
+impl GameStatus {
+    pub fn is_over(&self) -> bool {
+        matches!(self, GameStatus::Over(_))
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        match self {
+            GameStatus::Ongoing | GameStatus::Claimable(_) => None,
+            GameStatus::Over(outcome) => Some(*outcome),
+        }
+    }
+}
+
+impl Outcome {
+    pub fn draw(reason: Reason) -> Self {
+        Outcome { winner: None, reason }
+    }
+
+    // the result as written in PGN and sent to xboard
+    pub fn result_string(&self) -> &'static str {
+        match self.winner {
+            Some(Color::White) => "1-0",
+            Some(Color::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Reason::Checkmate => "checkmate",
+            Reason::Stalemate => "stalemate",
+            Reason::InsufficientMaterial => "insufficient material",
+            Reason::FiftyMoveRule => "50 move rule",
+            Reason::SeventyFiveMoveRule => "75 move rule",
+            Reason::ThreefoldRepetition => "threefold repetition",
+            Reason::FivefoldRepetition => "fivefold repetition",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.winner {
+            Some(color) => write!(f, "{} wins by {}", color, self.reason),
+            None => write!(f, "Draw by {}", self.reason),
+        }
+    }
+}
+
+impl Board {
+    /* history holds the zobrist keys of the positions before this one, oldest first, it is only
+    needed for the repetitions. Checkmate wins even if the mating move also hits the 75-move rule.
+    */
+    pub fn status(&self, history: &[u64]) -> GameStatus {
+        if generate_moves(self).is_empty() {
+            return GameStatus::Over(if self.is_check() {
+                Outcome { winner: Some(self.side_to_move.opposite()), reason: Reason::Checkmate }
+            } else {
+                Outcome::draw(Reason::Stalemate)
+            });
+        }
+
+        let repetitions = self.repetition_count(history);
+        if self.is_insufficient_material() {
+            GameStatus::Over(Outcome::draw(Reason::InsufficientMaterial))
+        } else if repetitions >= 5 {
+            GameStatus::Over(Outcome::draw(Reason::FivefoldRepetition))
+        } else if self.halfmove_clock >= 150 {
+            GameStatus::Over(Outcome::draw(Reason::SeventyFiveMoveRule))
+        } else if repetitions >= 3 {
+            GameStatus::Claimable(Reason::ThreefoldRepetition)
+        } else if self.halfmove_clock >= 100 {
+            GameStatus::Claimable(Reason::FiftyMoveRule)
+        } else {
+            GameStatus::Ongoing
+        }
+    }
+
+    // how many times the current position has appeared, counting this one
+    pub fn repetition_count(&self, history: &[u64]) -> usize {
+        // a capture or a pawn move can't be undone, nothing before it can repeat
+        let reversible = (self.halfmove_clock as usize).min(history.len());
+        1 + history[history.len() - reversible..].iter().filter(|&&key| key == self.hash).count()
+    }
+
+    // neither side can ever mate, whatever the moves: lone kings, a single minor piece,
+    // or only bishops that all stand on the same square color
+    pub fn is_insufficient_material(&self) -> bool {
+        let mut knights = 0;
+        let mut bishop_colors = [false; 2];
+        for (square, piece) in self.squares.iter().enumerate() {
+            match piece {
+                Some(Piece::Pawn(_)) | Some(Piece::Rook(_)) | Some(Piece::Queen(_)) => return false,
+                Some(Piece::Knight(_)) => knights += 1,
+                Some(Piece::Bishop(_)) => bishop_colors[(square / 8 + square % 8) % 2] = true,
+                Some(Piece::King(_)) | None => {},
+            }
+        }
+
+        let bishops_on_both_colors = bishop_colors[0] && bishop_colors[1];
+        let any_bishop = bishop_colors[0] || bishop_colors[1];
+        match knights {
+            0 => !bishops_on_both_colors,
+            1 => !any_bishop,
+            _ => false,
+        }
+    }
+}
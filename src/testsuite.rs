@@ -0,0 +1,184 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use crate::fen::Epd;
+use crate::movegen::Move;
+use crate::search::{self, SearchLimits, MATE_SCORE, MATE_THRESHOLD};
+use crate::tt::TranspositionTable;
+use crate::uci::score_to_uci;
+
+/* Runs the engine on test suites (WAC, STS, ...) to see if a change made it find more tactics.
+Every EPD position says what the right answer is: bm the best moves (any of them is fine), am
+moves to avoid, dm a mate in that many moves. The search gets the same limit on every position,
+a position counts as solved if the move of the last finished iteration is right.
+The time to solution is when the search found the right move and kept it until the end, the
+moves it likes at lower depths and drops later don't count.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct PositionResult {
+    pub id: String,
+    pub expected: String, // the operations checked, "bm Qg6" or "dm 3"
+    pub found: String, // the move played, in SAN
+    pub score: i32,
+    pub depth: u8,
+    pub solved: bool,
+    pub solved_at: Option<Duration>,
+    pub time: Duration,
+    pub nodes: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuiteSummary {
+    pub positions: usize,
+    pub solved: usize,
+    pub average_solution_time: Option<Duration>, // over the solved positions
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+// bm, am and dm all have to agree, a position with none of them can't be solved
+pub fn is_solution(epd: &Epd, movement: Move, score: i32) -> bool {
+    let mut checked = false;
+    if epd.operands("bm").is_some() {
+        checked = true;
+        if !epd.best_moves.contains(&movement) {
+            return false;
+        }
+    }
+    if epd.operands("am").is_some() {
+        checked = true;
+        if epd.avoid_moves.contains(&movement) {
+            return false;
+        }
+    }
+    if let Some(moves) = epd.direct_mate() {
+        checked = true;
+        let plies = MATE_SCORE - score;
+        if score <= MATE_THRESHOLD || (plies + 1) / 2 > moves {
+            return false;
+        }
+    }
+    checked
+}
+
+pub fn run_position(epd: &Epd, limits: &SearchLimits, tt: &mut TranspositionTable) -> PositionResult {
+    let stop = AtomicBool::new(false);
+    let mut solved_at = None;
+    let mut time = Duration::ZERO;
+    let mut depth = 0;
+
+    let result = search::iterative_deepening(&epd.board, limits, &stop, tt, |iteration, result, elapsed| {
+        depth = iteration;
+        time = elapsed;
+        if !is_solution(epd, result.best_move, result.score) {
+            solved_at = None;
+        } else if solved_at.is_none() {
+            solved_at = Some(elapsed);
+        }
+    });
+
+    let expected: Vec<String> = ["bm", "am", "dm"].iter()
+        .filter_map(|&opcode| epd.operands(opcode).map(|operands| format!("{} {}", opcode, operands.join(" "))))
+        .collect();
+    let id = epd.id().map(|id| id.to_string()).unwrap_or_else(|| epd.board.to_fen());
+
+    match result {
+        Some(result) => {
+            let solved = is_solution(epd, result.best_move, result.score);
+            PositionResult {
+                id,
+                expected: expected.join(", "),
+                found: result.best_move.to_san(&epd.board),
+                score: result.score,
+                depth,
+                solved,
+                solved_at: if solved { solved_at } else { None },
+                time,
+                nodes: result.nodes,
+            }
+        },
+        // mate or stalemate on the board, nothing to play
+        None => PositionResult {
+            id,
+            expected: expected.join(", "),
+            found: "-".to_string(),
+            score: 0,
+            depth: 0,
+            solved: false,
+            solved_at: None,
+            time,
+            nodes: 0,
+        },
+    }
+}
+
+// every position with the same limit, the hash table is cleared in between so they don't help each other
+pub fn run_suite(positions: &[Epd], limits: &SearchLimits, tt: &mut TranspositionTable, mut report: impl FnMut(&PositionResult)) -> Vec<PositionResult> {
+    positions.iter().map(|epd| {
+        tt.clear();
+        let result = run_position(epd, limits, tt);
+        report(&result);
+        result
+    }).collect()
+}
+
+pub fn summarize(results: &[PositionResult]) -> SuiteSummary {
+    let solution_times: Vec<Duration> = results.iter().filter_map(|result| result.solved_at).collect();
+    SuiteSummary {
+        positions: results.len(),
+        solved: results.iter().filter(|result| result.solved).count(),
+        average_solution_time: if solution_times.is_empty() {
+            None
+        } else {
+            Some(solution_times.iter().sum::<Duration>() / solution_times.len() as u32)
+        },
+        nodes: results.iter().map(|result| result.nodes).sum(),
+        time: results.iter().map(|result| result.time).sum(),
+    }
+}
+
+pub fn table_header() -> String {
+    format!("{:<20} {:<16} {:<8} {:>6} {:>5} {:>9} {:>12}  {}", "id", "expected", "found", "score", "depth", "time ms", "nodes", "result")
+}
+
+pub fn table_row(result: &PositionResult) -> String {
+    format!("{:<20} {:<16} {:<8} {:>6} {:>5} {:>9} {:>12}  {}",
+        result.id, result.expected, result.found, score_to_uci(result.score), result.depth,
+        result.time.as_millis(), result.nodes, if result.solved { "solved" } else { "failed" })
+}
+
+pub fn summary_text(summary: &SuiteSummary) -> String {
+    let average = summary.average_solution_time.map(|time| format!("{} ms", time.as_millis())).unwrap_or_else(|| "-".to_string());
+    format!("Solved: {}/{}\nAverage time to solution: {}\nNodes: {}\nTime: {} ms",
+        summary.solved, summary.positions, average, summary.nodes, summary.time.as_millis())
+}
+
+// written by hand, a JSON library for one output format isn't worth the dependency
+pub fn to_json(results: &[PositionResult], summary: &SuiteSummary) -> String {
+    let positions: Vec<String> = results.iter().map(|result| {
+        format!("{{\"id\":{},\"expected\":{},\"found\":{},\"score\":{},\"depth\":{},\"solved\":{},\"solved_at_ms\":{},\"time_ms\":{},\"nodes\":{}}}",
+            json_string(&result.id), json_string(&result.expected), json_string(&result.found),
+            json_string(&score_to_uci(result.score)), result.depth, result.solved,
+            result.solved_at.map(|time| time.as_millis().to_string()).unwrap_or_else(|| "null".to_string()),
+            result.time.as_millis(), result.nodes)
+    }).collect();
+    format!("{{\"positions\":[{}],\"summary\":{{\"positions\":{},\"solved\":{},\"average_solution_ms\":{},\"nodes\":{},\"time_ms\":{}}}}}",
+        positions.join(","), summary.positions, summary.solved,
+        summary.average_solution_time.map(|time| time.as_millis().to_string()).unwrap_or_else(|| "null".to_string()),
+        summary.nodes, summary.time.as_millis())
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            ch if (ch as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
+}
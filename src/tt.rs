@@ -0,0 +1,141 @@
+use crate::movegen::Move;
+use crate::search::MATE_THRESHOLD;
+
+pub const DEFAULT_HASH_MB: usize = 16;
+
+/* The transposition table remembers the result of every position searched, indexed by its
+zobrist key. The same position is reached through many move orders (e4 e5 Nf3 and Nf3 e5 e4),
+so when it shows up again with enough depth already searched we can reuse the score.
+It has a fixed number of slots, two positions can fall in the same slot and then one of them
+has to be thrown away, see store().
+*/
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bound {
+    Exact, // the score is the real value of the position
+    Lower, // the real value is at least the score (the search failed high)
+    Upper, // the real value is at most the score (the search failed low)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TTEntry {
+    pub key: u64,
+    pub depth: u8,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+    pub generation: u8, // which search stored it, older entries get replaced first
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Option<TTEntry>>,
+    generation: u8,
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_HASH_MB)
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        TranspositionTable {
+            entries: vec![None; Self::entries_for(size_mb)],
+            generation: 0,
+        }
+    }
+
+    fn entries_for(size_mb: usize) -> usize {
+        (size_mb.max(1) * 1024 * 1024 / std::mem::size_of::<Option<TTEntry>>()).max(1)
+    }
+
+    // drops everything stored, the new size is in megabytes
+    pub fn resize(&mut self, size_mb: usize) {
+        self.entries = vec![None; Self::entries_for(size_mb)];
+        self.generation = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+        self.generation = 0;
+    }
+
+    // called once per search so entries from older searches lose priority
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    // how full the table is in permille, what UCI calls hashfull
+    pub fn hashfull(&self) -> usize {
+        let sample = self.entries.len().min(1000);
+        let used = self.entries[..sample].iter().filter(|entry| entry.is_some()).count();
+        used * 1000 / sample
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    // the score comes back already adjusted to be relative to the root at the given ply
+    pub fn probe(&self, key: u64, ply: i32) -> Option<TTEntry> {
+        match self.entries[self.index(key)] {
+            Some(entry) if entry.key == key => Some(TTEntry { score: score_from_tt(entry.score, ply), ..entry }),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, key: u64, depth: u8, score: i32, bound: Bound, best_move: Option<Move>, ply: i32) {
+        let index = self.index(key);
+        let generation = self.generation;
+
+        // replacement policy: always overwrite entries from older searches and the same position,
+        // otherwise only replace a shallower search by a deeper one
+        if let Some(old) = &self.entries[index]
+            && old.generation == generation && old.key != key && old.depth > depth {
+            return;
+        }
+
+        // keep the old best move if this search didn't find one for the same position
+        let best_move = match (&self.entries[index], best_move) {
+            (Some(old), None) if old.key == key => old.best_move,
+            _ => best_move,
+        };
+
+        self.entries[index] = Some(TTEntry {
+            key,
+            depth,
+            score: score_to_tt(score, ply),
+            bound,
+            best_move,
+            generation,
+        });
+    }
+}
+
+/* Mate scores are stored as "mate in N plies from the root" during the search, but a position
+in the table can be reached at a different ply next time. So they go in the table relative to
+the position itself and get converted back when they come out.
+*/
+pub fn score_to_tt(score: i32, ply: i32) -> i32 {
+    if score > MATE_THRESHOLD {
+        score + ply
+    } else if score < -MATE_THRESHOLD {
+        score - ply
+    } else {
+        score
+    }
+}
+
+pub fn score_from_tt(score: i32, ply: i32) -> i32 {
+    if score > MATE_THRESHOLD {
+        score - ply
+    } else if score < -MATE_THRESHOLD {
+        score + ply
+    } else {
+        score
+    }
+}
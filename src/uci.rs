@@ -0,0 +1,326 @@
+use std::io::{self, BufRead};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::board::{Board, Color};
+use crate::book::{BookMode, OpeningBook, DEFAULT_BOOK_DEPTH};
+use crate::movegen::Move;
+use crate::search::{self, SearchLimits, MATE_SCORE, MAX_DEPTH, MATE_THRESHOLD};
+use crate::tt::{TranspositionTable, DEFAULT_HASH_MB};
+
+const ENGINE_NAME: &str = "rusty-chess-engine";
+const ENGINE_AUTHOR: &str = "Dara3n";
+const DEFAULT_DEPTH: u8 = 4;
+const MAX_HASH_MB: usize = 1024;
+const MAX_BOOK_DEPTH: u16 = 100;
+
+/* UCI (Universal Chess Interface) is the text protocol GUIs use to talk to engines.
+The GUI sends commands on stdin (position, go, stop...) and the engine answers on stdout,
+the only thing it really needs from us is "bestmove" in coordinate notation (e2e4, e7e8q).
+The search runs on its own thread so we can keep reading "stop" and "quit" while thinking.
+*/
+pub struct Uci {
+    board: Board,
+    depth: u8, // used when "go" comes without any limit
+    chess960: bool, // UCI_Chess960, castling is sent and read as king takes rook
+    stop: Arc<AtomicBool>,
+    search_thread: Option<JoinHandle<()>>,
+    tt: Arc<Mutex<TranspositionTable>>, // shared with the search thread, kept between searches
+    book_file: String,
+    book: Option<OpeningBook>,
+    book_depth: u16,
+    book_best: bool,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct GoParams {
+    pub depth: Option<u8>,
+    pub movetime: Option<u64>,
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u64>,
+    pub infinite: bool,
+}
+
+impl GoParams {
+    pub fn to_limits(&self, side: Color) -> SearchLimits {
+        let (time_left, increment) = match side {
+            Color::White => (self.wtime, self.winc),
+            Color::Black => (self.btime, self.binc),
+        };
+        SearchLimits {
+            depth: self.depth,
+            move_time: self.movetime.map(Duration::from_millis),
+            time_left: time_left.map(Duration::from_millis),
+            increment: Duration::from_millis(increment.unwrap_or(0)),
+            moves_to_go: self.movestogo.map(|moves| moves as u32),
+            infinite: self.infinite,
+        }
+    }
+}
+
+impl Default for Uci {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uci {
+    pub fn new() -> Self {
+        Uci {
+            board: Board::default(),
+            depth: DEFAULT_DEPTH,
+            chess960: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            search_thread: None,
+            tt: Arc::new(Mutex::new(TranspositionTable::default())),
+            book_file: String::new(),
+            book: None,
+            book_depth: DEFAULT_BOOK_DEPTH,
+            book_best: false,
+        }
+    }
+
+    // answers the "uci" command the GUI opens with, then keeps reading commands until "quit"
+    pub fn run(&mut self) {
+        self.handle_command("uci");
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if !self.handle_command(&line) {
+                break;
+            }
+        }
+        self.stop_search();
+    }
+
+    // returns false when the engine has to exit
+    pub fn handle_command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = tokens.first() else { return true };
+
+        match command {
+            "uci" => {
+                println!("id name {}", ENGINE_NAME);
+                println!("id author {}", ENGINE_AUTHOR);
+                println!("option name Depth type spin default {} min 1 max {}", DEFAULT_DEPTH, MAX_DEPTH);
+                println!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH_MB, MAX_HASH_MB);
+                println!("option name Clear Hash type button");
+                println!("option name UCI_Chess960 type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookDepth type spin default {} min 0 max {}", DEFAULT_BOOK_DEPTH, MAX_BOOK_DEPTH);
+                println!("option name BookBest type check default false");
+                println!("uciok");
+            },
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                self.wait_for_search();
+                self.board = Board::default();
+                self.tt.lock().unwrap().clear();
+            },
+            "setoption" => {
+                self.wait_for_search();
+                self.set_option(&tokens[1..]);
+            },
+            "position" => {
+                self.wait_for_search();
+                match Self::parse_position_chess960(&tokens[1..], self.chess960) {
+                    Ok(board) => self.board = board,
+                    Err(e) => println!("info string invalid position: {}", e),
+                }
+            },
+            "go" => {
+                self.wait_for_search();
+                let params = Self::parse_go(&tokens[1..]);
+                self.start_search(params);
+            },
+            "stop" => self.stop_search(),
+            "quit" => return false,
+            _ => {} // the protocol says unknown commands are ignored
+        }
+        true
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    fn set_option(&mut self, tokens: &[&str]) {
+        // setoption name <id> [value <x>], names can have spaces
+        let value_index = tokens.iter().position(|&t| t == "value");
+        let name_end = value_index.unwrap_or(tokens.len());
+        let name = tokens.get(1..name_end).unwrap_or_default().join(" ");
+        let value = value_index.map(|i| tokens[i + 1..].join(" ")).unwrap_or_default();
+
+        match name.to_lowercase().as_str() {
+            "depth" => match value.parse::<u8>() {
+                Ok(depth) if (1..=MAX_DEPTH).contains(&depth) => self.depth = depth,
+                _ => println!("info string invalid depth {}", value),
+            },
+            "hash" => match value.parse::<usize>() {
+                Ok(size_mb) if (1..=MAX_HASH_MB).contains(&size_mb) => self.tt.lock().unwrap().resize(size_mb),
+                _ => println!("info string invalid hash size {}", value),
+            },
+            "clear hash" => self.tt.lock().unwrap().clear(),
+            "uci_chess960" => match value.as_str() {
+                "true" => self.chess960 = true,
+                "false" => self.chess960 = false,
+                _ => println!("info string invalid UCI_Chess960 value {}", value),
+            },
+            "bookfile" => {
+                self.book_file = if value == "<empty>" { String::new() } else { value };
+                self.load_book();
+            },
+            "bookdepth" => match value.parse::<u16>() {
+                Ok(depth) if depth <= MAX_BOOK_DEPTH => {
+                    self.book_depth = depth;
+                    if let Some(book) = &mut self.book {
+                        book.max_depth = depth;
+                    }
+                },
+                _ => println!("info string invalid book depth {}", value),
+            },
+            "bookbest" => match value.as_str() {
+                "true" | "false" => {
+                    self.book_best = value == "true";
+                    if let Some(book) = &mut self.book {
+                        book.mode = if self.book_best { BookMode::Best } else { BookMode::Weighted };
+                    }
+                },
+                _ => println!("info string invalid BookBest value {}", value),
+            },
+            _ => println!("info string unknown option {}", name),
+        }
+    }
+
+    // an empty BookFile turns the book off, a broken one is reported and the engine plays without it
+    fn load_book(&mut self) {
+        self.book = None;
+        if self.book_file.is_empty() {
+            return;
+        }
+        match OpeningBook::open(&self.book_file) {
+            Ok(mut book) => {
+                book.max_depth = self.book_depth;
+                book.mode = if self.book_best { BookMode::Best } else { BookMode::Weighted };
+                println!("info string book {} loaded, {} entries", self.book_file, book.len());
+                self.book = Some(book);
+            },
+            Err(e) => println!("info string can't use the book: {}", e),
+        }
+    }
+
+    pub fn has_book(&self) -> bool {
+        self.book.is_some()
+    }
+
+    pub fn parse_position(tokens: &[&str]) -> Result<Board, String> {
+        Self::parse_position_chess960(tokens, false)
+    }
+
+    // with UCI_Chess960 on the moves castle as king takes rook, e1g1 is only ever a king move
+    pub fn parse_position_chess960(tokens: &[&str], chess960: bool) -> Result<Board, String> {
+        let moves_index = tokens.iter().position(|&t| t == "moves").unwrap_or(tokens.len());
+
+        let mut board = match tokens.first() {
+            Some(&"startpos") => Board::default(),
+            Some(&"fen") => Board::from_fen(&tokens[1..moves_index].join(" "))?,
+            _ => return Err("expected startpos or fen".to_string()),
+        };
+        board.chess960 |= chess960;
+
+        for movestring in tokens.iter().skip(moves_index + 1) {
+            let movement = Move::string_to_move(movestring, &board)?;
+            board.make_move(movement);
+        }
+        Ok(board)
+    }
+
+    pub fn parse_go(tokens: &[&str]) -> GoParams {
+        let mut params = GoParams::default();
+        let mut iter = tokens.iter();
+
+        while let Some(&token) = iter.next() {
+            let mut next_number = || iter.next().and_then(|value| value.parse::<u64>().ok());
+            match token {
+                "depth" => params.depth = next_number().map(|d| d.clamp(1, MAX_DEPTH as u64) as u8),
+                "movetime" => params.movetime = next_number(),
+                "wtime" => params.wtime = next_number(),
+                "btime" => params.btime = next_number(),
+                "winc" => params.winc = next_number(),
+                "binc" => params.binc = next_number(),
+                "movestogo" => params.movestogo = next_number(),
+                "infinite" => params.infinite = true,
+                _ => {}
+            }
+        }
+        params
+    }
+
+    fn start_search(&mut self, params: GoParams) {
+        // book moves are answered right away, except in infinite mode (analysis) where the GUI wants a search
+        if !params.infinite && let Some(movement) = self.book.as_ref().and_then(|book| book.pick(&self.board)) {
+            println!("bestmove {}", movement.to_uci_for(&self.board));
+            return;
+        }
+        self.stop = Arc::new(AtomicBool::new(false));
+        let mut limits = params.to_limits(self.board.side_to_move);
+        // a bare "go" searches to the depth set in the options
+        if limits.depth.is_none() && !limits.infinite && limits.time_budget().is_none() {
+            limits.depth = Some(self.depth);
+        }
+
+        let board = self.board;
+        let stop = Arc::clone(&self.stop);
+        let tt = Arc::clone(&self.tt);
+        self.search_thread = Some(thread::spawn(move || search_and_report(board, limits, stop, tt)));
+    }
+
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.wait_for_search();
+    }
+
+    // commands that touch the board have to wait until the running search has sent its bestmove
+    fn wait_for_search(&mut self) {
+        if let Some(handle) = self.search_thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn search_and_report(board: Board, limits: SearchLimits, stop: Arc<AtomicBool>, tt: Arc<Mutex<TranspositionTable>>) {
+    let mut tt = tt.lock().unwrap();
+
+    let result = search::iterative_deepening(&board, &limits, &stop, &mut tt, |depth, result, elapsed| {
+        let elapsed = elapsed.as_millis().max(1) as u64;
+        println!("info depth {} score {} nodes {} time {} nps {} pv {}",
+            depth, score_to_uci(result.score), result.nodes, elapsed,
+            result.nodes * 1000 / elapsed, result.best_move.to_uci_for(&board));
+    });
+    println!("info hashfull {}", tt.hashfull());
+
+    // in infinite mode the GUI expects bestmove only after it sends "stop"
+    while limits.infinite && !stop.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    match result {
+        Some(result) => println!("bestmove {}", result.best_move.to_uci_for(&board)),
+        None => println!("bestmove 0000"),
+    }
+}
+
+// the score is from the side to move, which is the engine when it is thinking
+pub fn score_to_uci(score: i32) -> String {
+    if score.abs() > MATE_THRESHOLD {
+        let plies = MATE_SCORE - score.abs();
+        let moves = (plies + 1) / 2;
+        format!("mate {}", if score > 0 { moves } else { -moves })
+    } else {
+        format!("cp {}", score)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::board::{Board, Color, Piece, CASTLING_RIGHTS, STANDARD_CASTLING_ROOKS};
+use crate::fen::FenError;
+
+/* Checks that a position could come up in a real game, or at least that the engine can play
+from it without falling over. The FEN parser already refuses the worst problems on its own,
+validate() is for boards put together square by square (a board editor) and for the strict FEN
+mode, which also refuses the smaller lies: castling rights for a king or rook that isn't there
+any more, or an en passant square without the pawn that just moved.
+Every problem is reported, not only the first one, so an editor can show them all at once.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PositionError {
+    MissingKing(Color),
+    TooManyKings(Color),
+    PawnOnBackRank(usize), // the square
+    OpponentInCheck,
+    CastlingWithoutKing(u8), // the castling right, see board::CASTLING_RIGHTS
+    CastlingWithoutRook(u8),
+    BadEnPassant(u8), // the en passant square
+}
+
+impl std::fmt::Display for PositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PositionError::MissingKing(color) => write!(f, "{} has no king", color),
+            PositionError::TooManyKings(color) => write!(f, "{} has more than one king", color),
+            PositionError::PawnOnBackRank(square) => write!(f, "pawn on {}", Board::square_to_string(*square as u8)),
+            PositionError::OpponentInCheck => write!(f, "the side not to move is in check"),
+            PositionError::CastlingWithoutKing(right) => write!(f, "castling right {} but the king isn't on its square", right_name(*right)),
+            PositionError::CastlingWithoutRook(right) => write!(f, "castling right {} but the rook isn't on its square", right_name(*right)),
+            PositionError::BadEnPassant(square) => write!(f, "en passant square {} without a pawn that just moved two squares", Board::square_to_string(*square)),
+        }
+    }
+}
+
+fn right_name(right: u8) -> char {
+    let index = CASTLING_RIGHTS.iter().position(|&r| r == right).unwrap_or(0);
+    ['K', 'Q', 'k', 'q'][index]
+}
+
+impl Board {
+    pub fn validate(&self) -> Vec<PositionError> {
+        let mut problems = Vec::new();
+
+        for color in [Color::White, Color::Black] {
+            match self.pieces(Piece::King(color)).count_ones() {
+                0 => problems.push(PositionError::MissingKing(color)),
+                1 => {},
+                _ => problems.push(PositionError::TooManyKings(color)),
+            }
+        }
+
+        for square in (0..8).chain(56..64) {
+            if let Some(Piece::Pawn(_)) = self.squares[square] {
+                problems.push(PositionError::PawnOnBackRank(square));
+            }
+        }
+
+        // the side that just moved can't have left its king attacked
+        let them = self.side_to_move.opposite();
+        let their_king = self.pieces(Piece::King(them));
+        if their_king.count_ones() == 1 &&
+            self.attackers(their_king.trailing_zeros() as usize, self.side_to_move, self.occupied()) != 0 {
+            problems.push(PositionError::OpponentInCheck);
+        }
+
+        for (index, &right) in CASTLING_RIGHTS.iter().enumerate() {
+            if self.castling_rights & right == 0 {
+                continue;
+            }
+            let color = if index < 2 { Color::White } else { Color::Black };
+            let back_rank = if index < 2 { 0..8 } else { 56..64 };
+            // from the bitboard, an editor doesn't have to keep white_king/black_king up to date
+            let kings = self.pieces(Piece::King(color));
+            let king = kings.trailing_zeros() as usize;
+            let rook = self.castling_rooks[index] as usize;
+
+            // in normal chess only e1/e8 and the corner rooks can still castle
+            let king_home = kings.count_ones() == 1 && back_rank.contains(&king) && (self.chess960 || king % 8 == 4);
+            if !king_home {
+                problems.push(PositionError::CastlingWithoutKing(right));
+            }
+            let kingside = index % 2 == 0;
+            let rook_home = self.squares[rook] == Some(Piece::Rook(color)) && back_rank.contains(&rook) &&
+                (self.chess960 || rook == STANDARD_CASTLING_ROOKS[index] as usize) &&
+                (!king_home || (rook > king) == kingside);
+            if !rook_home {
+                problems.push(PositionError::CastlingWithoutRook(right));
+            }
+        }
+
+        if let Some(square) = self.en_passant_square {
+            // the pawn that moved two squares stands in front of the en passant square (seen from
+            // its side), the square it jumped over and the one it came from are empty
+            let square = square as usize;
+            let (rank, pawn, origin) = match self.side_to_move {
+                Color::White => (5, square.wrapping_sub(8), square + 8),
+                Color::Black => (2, square + 8, square.wrapping_sub(8)),
+            };
+            if square / 8 != rank || self.squares[pawn] != Some(Piece::Pawn(them)) ||
+                self.squares[square].is_some() || self.squares[origin].is_some() {
+                problems.push(PositionError::BadEnPassant(square as u8));
+            }
+        }
+
+        problems
+    }
+
+    // from_fen plus validate(): refuses anything validate() finds
+    pub fn from_fen_strict(fen: &str) -> Result<Self, FenError> {
+        let board = Self::from_fen(fen)?;
+        let problems = board.validate();
+        if !problems.is_empty() {
+            return Err(FenError::IllegalPosition { problems });
+        }
+        Ok(board)
+    }
+}
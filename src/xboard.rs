@@ -0,0 +1,240 @@
+use std::io::{self, BufRead};
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use crate::board::{Board, Color};
+use crate::game::Game;
+use crate::movegen::Move;
+use crate::search::{self, SearchLimits, MAX_DEPTH};
+use crate::status::{GameStatus, Outcome};
+use crate::tt::TranspositionTable;
+
+const ENGINE_NAME: &str = "rusty-chess-engine";
+const DEFAULT_DEPTH: u8 = 4;
+
+/* CECP (Chess Engine Communication Protocol) is what XBoard/WinBoard speak.
+Unlike UCI the engine keeps track of the game itself: the GUI sends the opponent moves
+one by one ("usermove e2e4") and the engine answers with "move e7e5" when it is its turn.
+In force mode the engine just records the moves it is sent without thinking.
+*/
+pub struct XBoard {
+    game: Game,
+    engine_color: Option<Color>, // None while in force mode
+    post: bool,
+    max_depth: Option<u8>, // set by "sd"
+    time_control: TimeControl,
+    time_left: Option<u64>, // our clock in centiseconds, sent by "time"
+    tt: TranspositionTable,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeControl {
+    // no "level" or "st" received yet, search to a fixed depth
+    None,
+    // "level MPS BASE INC", moves_per_session is 0 for the whole game
+    Conventional { moves_per_session: u32, base_ms: u64, increment_ms: u64 },
+    // "st N", a fixed time for every move
+    FixedTime { move_time_ms: u64 },
+}
+
+impl Default for XBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XBoard {
+    pub fn new() -> Self {
+        XBoard {
+            game: Game::default(),
+            engine_color: Some(Color::Black),
+            post: false,
+            max_depth: None,
+            time_control: TimeControl::None,
+            time_left: None,
+            tt: TranspositionTable::default(),
+        }
+    }
+
+    pub fn run(&mut self) {
+        // the "xboard" command itself needs no answer, just an empty line is fine
+        println!();
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if !self.handle_command(&line) {
+                break;
+            }
+        }
+    }
+
+    // returns false when the engine has to exit
+    pub fn handle_command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = tokens.first() else { return true };
+
+        match command {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "otim" => {},
+            "protover" => {
+                println!("feature myname=\"{}\" ping=1 setboard=1 usermove=1 san=0 time=1 draw=0 sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 memory=1", ENGINE_NAME);
+                println!("feature done=1");
+            },
+            "ping" => println!("pong {}", tokens.get(1).unwrap_or(&"")),
+            "new" => {
+                self.game = Game::default();
+                self.engine_color = Some(Color::Black);
+                self.max_depth = None;
+                self.tt.clear();
+            },
+            "force" => self.engine_color = None,
+            "go" => {
+                self.engine_color = Some(self.game.board().side_to_move);
+                self.engine_move();
+            },
+            "playother" => self.engine_color = Some(self.game.board().side_to_move.opposite()),
+            "usermove" => {
+                if let Some(movestring) = tokens.get(1) {
+                    self.user_move(movestring);
+                }
+            },
+            "undo" => self.undo(1),
+            "remove" => self.undo(2),
+            "setboard" => match Game::from_fen(&tokens[1..].join(" ")) {
+                Ok(game) => self.game = game,
+                Err(e) => println!("tellusererror Illegal position: {}", e),
+            },
+            "level" => {
+                if let Some(time_control) = Self::parse_level(&tokens[1..]) {
+                    self.time_control = time_control;
+                }
+            },
+            "st" => {
+                if let Some(seconds) = tokens.get(1).and_then(|t| t.parse::<u64>().ok()) {
+                    self.time_control = TimeControl::FixedTime { move_time_ms: seconds * 1000 };
+                }
+            },
+            "sd" => self.max_depth = tokens.get(1).and_then(|t| t.parse::<u8>().ok()).map(|d| d.clamp(1, MAX_DEPTH)),
+            "time" => self.time_left = tokens.get(1).and_then(|t| t.parse().ok()),
+            "memory" => {
+                if let Some(size_mb) = tokens.get(1).and_then(|t| t.parse::<usize>().ok()) {
+                    self.tt.resize(size_mb);
+                }
+            },
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "result" => self.engine_color = None,
+            "quit" => return false,
+            // protover 2 engines can get moves without "usermove" from old interfaces
+            _ => {
+                if Move::string_to_move(command, self.game.board()).is_ok() {
+                    self.user_move(command);
+                } else {
+                    println!("Error (unknown command): {}", command);
+                }
+            },
+        }
+        true
+    }
+
+    pub fn board(&self) -> &Board {
+        self.game.board()
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    // "level 40 5 0" or "level 0 2:30 1", base is in minutes and increment in seconds
+    pub fn parse_level(tokens: &[&str]) -> Option<TimeControl> {
+        if tokens.len() < 3 {
+            return None;
+        }
+        let moves_per_session = tokens[0].parse().ok()?;
+        let base_ms = match tokens[1].split_once(':') {
+            Some((minutes, seconds)) => minutes.parse::<u64>().ok()? * 60_000 + seconds.parse::<u64>().ok()? * 1000,
+            None => tokens[1].parse::<u64>().ok()? * 60_000,
+        };
+        let increment_ms = (tokens[2].parse::<f64>().ok()? * 1000.0) as u64;
+
+        Some(TimeControl::Conventional { moves_per_session, base_ms, increment_ms })
+    }
+
+    fn user_move(&mut self, movestring: &str) {
+        match self.game.push_str(movestring) {
+            Ok(_) => {
+                if self.report_result() {
+                    self.engine_color = None;
+                } else if self.engine_color == Some(self.game.board().side_to_move) {
+                    self.engine_move();
+                }
+            },
+            Err(_) => println!("Illegal move: {}", movestring),
+        }
+    }
+
+    fn undo(&mut self, moves: usize) {
+        for _ in 0..moves {
+            self.game.pop();
+        }
+    }
+
+    fn search_limits(&self) -> SearchLimits {
+        let mut limits = match self.time_control {
+            TimeControl::None => SearchLimits::depth(DEFAULT_DEPTH),
+            TimeControl::FixedTime { move_time_ms } => SearchLimits::move_time(Duration::from_millis(move_time_ms)),
+            TimeControl::Conventional { moves_per_session, base_ms, increment_ms } => {
+                let moves_to_go = if moves_per_session == 0 {
+                    None
+                } else {
+                    let played = self.game.board().fullmove_number.saturating_sub(1) % moves_per_session;
+                    Some(moves_per_session - played)
+                };
+                SearchLimits {
+                    time_left: Some(Duration::from_millis(self.time_left.map(|cs| cs * 10).unwrap_or(base_ms))),
+                    increment: Duration::from_millis(increment_ms),
+                    moves_to_go,
+                    ..Default::default()
+                }
+            },
+        };
+        if self.max_depth.is_some() {
+            limits.depth = self.max_depth;
+        }
+        limits
+    }
+
+    fn engine_move(&mut self) {
+        let limits = self.search_limits();
+        let stop = AtomicBool::new(false);
+        let post = self.post;
+
+        let result = search::iterative_deepening(self.game.board(), &limits, &stop, &mut self.tt, |depth, result, elapsed| {
+            if post {
+                // ply score time(centiseconds) nodes pv, score from the engine's point of view
+                println!("{} {} {} {} {}", depth, result.score, elapsed.as_millis() / 10, result.nodes, result.best_move.to_uci());
+            }
+        });
+
+        let Some(result) = result else {
+            self.report_result();
+            return;
+        };
+
+        println!("move {}", result.best_move.to_uci());
+        self.game.push(result.best_move);
+        if self.report_result() {
+            self.engine_color = None;
+        }
+    }
+
+    // prints the result if the game is over and returns whether it is. In CECP the engine claims
+    // the 50-move and repetition draws by sending the result itself
+    fn report_result(&self) -> bool {
+        let outcome = match self.game.status() {
+            GameStatus::Over(outcome) => outcome,
+            GameStatus::Claimable(reason) => Outcome::draw(reason),
+            GameStatus::Ongoing => return false,
+        };
+        println!("{} {{{}}}", outcome.result_string(), outcome);
+        true
+    }
+}
@@ -0,0 +1,102 @@
+use crate::board::{Board, Color, Piece};
+
+/* Zobrist hashing gives every (piece, square) pair, the side to move, every castling rights
+combination and every en passant file a random 64 bit number. The key of a position is the xor
+of the numbers of everything in it, so making a move only needs a few xors to update it:
+xoring a number in and out again cancels itself.
+The numbers are generated at compile time with a fixed seed so keys are the same on every run.
+*/
+pub struct ZobristKeys {
+    pub pieces: [[u64; 64]; 12],
+    pub side_to_move: u64,
+    pub castling: [u64; 16],
+    pub en_passant: [u64; 8],
+}
+
+pub const KEYS: ZobristKeys = generate_keys();
+
+// splitmix64, small and good enough for hashing keys
+const fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+const fn generate_keys() -> ZobristKeys {
+    let mut state: u64 = 0x2545F4914F6CDD1D;
+    let mut pieces = [[0u64; 64]; 12];
+    let mut piece = 0;
+    while piece < 12 {
+        let mut square = 0;
+        while square < 64 {
+            pieces[piece][square] = next_random(&mut state);
+            square += 1;
+        }
+        piece += 1;
+    }
+
+    let side_to_move = next_random(&mut state);
+
+    let mut castling = [0u64; 16];
+    let mut i = 0;
+    while i < 16 {
+        castling[i] = next_random(&mut state);
+        i += 1;
+    }
+
+    let mut en_passant = [0u64; 8];
+    let mut file = 0;
+    while file < 8 {
+        en_passant[file] = next_random(&mut state);
+        file += 1;
+    }
+
+    ZobristKeys { pieces, side_to_move, castling, en_passant }
+}
+
+pub fn piece_index(piece: &Piece) -> usize {
+    let offset = match piece.color() {
+        Color::White => 0,
+        Color::Black => 6,
+    };
+    let kind = match piece {
+        Piece::Pawn(_) => 0,
+        Piece::Knight(_) => 1,
+        Piece::Bishop(_) => 2,
+        Piece::Rook(_) => 3,
+        Piece::Queen(_) => 4,
+        Piece::King(_) => 5,
+    };
+    offset + kind
+}
+
+pub fn piece_key(piece: &Piece, square: usize) -> u64 {
+    KEYS.pieces[piece_index(piece)][square]
+}
+
+pub fn en_passant_key(square: Option<u8>) -> u64 {
+    match square {
+        Some(square) => KEYS.en_passant[(square % 8) as usize],
+        None => 0,
+    }
+}
+
+impl Board {
+    // computes the key from scratch, make_move keeps it updated incrementally
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for (square, piece) in self.squares.iter().enumerate() {
+            if let Some(piece) = piece {
+                hash ^= piece_key(piece, square);
+            }
+        }
+        if self.side_to_move == Color::Black {
+            hash ^= KEYS.side_to_move;
+        }
+        hash ^= KEYS.castling[self.castling_rights as usize];
+        hash ^= en_passant_key(self.en_passant_square);
+        hash
+    }
+}
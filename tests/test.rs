@@ -1,13 +1,14 @@
-use std::fs::exists;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
-use chess_engine_rust::{board::{self, Board, Color}, eval, movegen::{generate_moves, Move}, search::minimax_best_move};
+use chess_engine_rust::{bitboard::{bishop_attacks, rook_attacks, square_bb, KNIGHT_ATTACKS, PAWN_ATTACKS}, book::{decode_move, encode_move, BookBuilder, BookEntry, BookMode, MoveStats, OpeningBook}, board::{file_of, rank_of, square_at, Board, Color, Piece}, fen::{Epd, FenError, FenField, START_FEN}, movegen::{generate_captures, generate_moves, is_valid_move, Move}, eval, game::Game, ordering::{mvv_lva, MoveOrdering}, perft::{divide, perft}, pgn::{read_all, PgnEval, PgnGame, PgnReader}, san::Notation, status::{GameStatus, Outcome, Reason}, search::{alpha_beta_search, iterative_deepening, minimax_best_move, SearchLimits, MATE_SCORE, MATE_THRESHOLD, MAX_DEPTH}, testsuite, tt::{Bound, TranspositionTable}, validate::PositionError, uci::Uci, xboard::{TimeControl, XBoard}};
 #[test]
 fn test_initial_position() {
     let board = Board::default(); 
 
     assert!(board.en_passant_square.is_none());
     assert_eq!(board.castling_rights, 0b1111);
-    assert_eq!(board.fullmove_number, 0);
+    assert_eq!(board.fullmove_number, 1);
     assert_eq!(board.halfmove_clock, 0);
 
 }
@@ -34,7 +35,7 @@ fn test_fen() {
 fn test_movegen() {
     let mut board = Board::default();
 
-    let legal_moves = generate_moves(&mut board);
+    let legal_moves = generate_moves(&board);
 
     assert_eq!(legal_moves.len(), 20);
 
@@ -59,7 +60,7 @@ fn test_check() {
     board = Board::from_fen(fen).unwrap();
     assert!(board.is_check());
 
-    fen = "4K3/3p4/8/8/8/8/8/4k3 w - - 0 1";
+    fen = "4k3/8/8/8/8/8/3p4/4K3 w - - 0 1";
     board = Board::from_fen(fen).unwrap();
     assert!(board.is_check());
 
@@ -70,9 +71,9 @@ fn test_check() {
 
 #[test]
 fn test_stalemate() {
-    let mut fen = "7k/5Q2/8/8/8/8/8/7K b - - 0 1";
-    let mut board = Board::from_fen(fen).unwrap();
-    let moves = generate_moves(&mut board);
+    let fen = "7k/5Q2/8/8/8/8/8/7K b - - 0 1";
+    let board = Board::from_fen(fen).unwrap();
+    let moves = generate_moves(&board);
     assert_eq!(moves.len(), 0);
     assert!(!board.is_check());
 }
@@ -80,7 +81,7 @@ fn test_stalemate() {
 #[test]
 fn test_string_to_squre() {
     let coords = "h2";
-    let square = Board::str_to_square(&coords);
+    let square = Board::str_to_square(coords);
     if square.is_ok() {
         assert_eq!(square, Ok(15));
     }
@@ -92,7 +93,7 @@ fn test_string_to_move(){
     let str_movement = "h2h4";
     let mut board = Board::default();
 
-    let movement = Move::string_to_move(&str_movement, &board);
+    let movement = Move::string_to_move(str_movement, &board);
 
     board.make_move(movement.unwrap());
 }
@@ -102,7 +103,7 @@ fn test_h_moves(){
     let mut board = Board::default();
 
     let mut exist = false;
-    let moves = generate_moves(&mut board);
+    let moves = generate_moves(&board);
     for movement in  moves {
         if movement.get_from() == Board::str_to_square("h2").unwrap() as u16 {
             board.make_move(movement);
@@ -116,4 +117,1313 @@ fn test_h_moves(){
 
 
     
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_uci_position(){
+    let tokens: Vec<&str> = "startpos moves e2e4 e7e5 g1f3".split_whitespace().collect();
+    let board = Uci::parse_position(&tokens).unwrap();
+
+    assert_eq!(board.side_to_move, Color::Black);
+    assert_eq!(board.squares[Board::str_to_square("f3").unwrap()], Some(Piece::Knight(Color::White)));
+    assert_eq!(board.squares[Board::str_to_square("e5").unwrap()], Some(Piece::Pawn(Color::Black)));
+
+    let tokens: Vec<&str> = "startpos moves e2e5".split_whitespace().collect();
+    assert!(Uci::parse_position(&tokens).is_err());
+}
+
+#[test]
+fn test_uci_go(){
+    let tokens: Vec<&str> = "wtime 60000 btime 55000 winc 1000 binc 1000 movestogo 20".split_whitespace().collect();
+    let params = Uci::parse_go(&tokens);
+
+    assert_eq!(params.wtime, Some(60000));
+    assert_eq!(params.btime, Some(55000));
+    assert_eq!(params.winc, Some(1000));
+    assert_eq!(params.movestogo, Some(20));
+    assert_eq!(params.depth, None);
+    assert!(!params.infinite);
+
+    let params = Uci::parse_go(&["depth", "3"]);
+    assert_eq!(params.depth, Some(3));
+}
+
+#[test]
+fn test_xboard_level(){
+    assert_eq!(XBoard::parse_level(&["40", "5", "0"]),
+        Some(TimeControl::Conventional { moves_per_session: 40, base_ms: 300_000, increment_ms: 0 }));
+    assert_eq!(XBoard::parse_level(&["0", "2:30", "1"]),
+        Some(TimeControl::Conventional { moves_per_session: 0, base_ms: 150_000, increment_ms: 1000 }));
+    assert_eq!(XBoard::parse_level(&["40", "5"]), None);
+}
+
+#[test]
+fn test_xboard_moves(){
+    let mut xboard = XBoard::new();
+    xboard.handle_command("new");
+    xboard.handle_command("force");
+    xboard.handle_command("usermove e2e4");
+    xboard.handle_command("usermove e7e5");
+    assert_eq!(xboard.board().side_to_move, Color::White);
+
+    xboard.handle_command("remove");
+    assert_eq!(*xboard.board(), Board::default());
+}
+
+#[test]
+fn test_xboard_draw_claims(){
+    // the 50-move rule: the engine plays black and claims the draw instead of answering
+    let mut xboard = XBoard::new();
+    xboard.handle_command("new");
+    xboard.handle_command("setboard 4k3/8/8/8/8/8/4P3/4K3 w - - 99 80");
+    xboard.handle_command("usermove e1d1");
+    assert_eq!(xboard.game().status(), GameStatus::Claimable(Reason::FiftyMoveRule));
+    assert_eq!(xboard.game().moves().len(), 1);
+    assert_eq!(xboard.board().side_to_move, Color::Black);
+
+    // threefold repetition, with the engine on white after the knights went back and forth
+    let mut xboard = XBoard::new();
+    xboard.handle_command("new");
+    xboard.handle_command("force");
+    for movestring in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"] {
+        xboard.handle_command(&format!("usermove {}", movestring));
+    }
+    xboard.handle_command("playother");
+    xboard.handle_command("usermove f6g8");
+    assert_eq!(xboard.game().status(), GameStatus::Claimable(Reason::ThreefoldRepetition));
+    assert_eq!(xboard.game().moves().len(), 8);
+    assert_eq!(xboard.board().side_to_move, Color::White);
+}
+
+#[test]
+fn test_zobrist_incremental(){
+    let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    assert_eq!(board.hash, board.compute_hash());
+
+    // walk a few plies always taking the first move and check every make/unmake pair on the way
+    for _ in 0..20 {
+        let moves = generate_moves(&board);
+        if moves.is_empty() {
+            break;
+        }
+        for movement in &moves {
+            let before = board;
+            let undo = board.make_move(*movement);
+            assert_eq!(board.hash, board.compute_hash(), "after {:?}", movement);
+            board.unmake_move(*movement, undo);
+            assert_eq!(board, before);
+        }
+        board.make_move(moves[moves.len() / 2]);
+    }
+}
+
+#[test]
+fn test_zobrist_transposition(){
+    let mut board = Board::default();
+    for movestring in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+        board.make_move(Move::string_to_move(movestring, &board).unwrap());
+    }
+    assert_eq!(board.hash, Board::default().hash);
+
+    let mut board = Board::default();
+    board.make_move(Move::string_to_move("e2e4", &board).unwrap());
+    // same pieces but no en passant square
+    let mut no_en_passant = board;
+    no_en_passant.en_passant_square = None;
+    assert_ne!(board.hash, no_en_passant.compute_hash());
+}
+
+#[test]
+fn test_transposition_table(){
+    let mut tt = TranspositionTable::new(1);
+    let movement = Move::normal(12, 28);
+
+    tt.store(42, 3, 150, Bound::Exact, Some(movement), 2);
+    let entry = tt.probe(42, 2).unwrap();
+    assert_eq!((entry.depth, entry.score, entry.bound, entry.best_move), (3, 150, Bound::Exact, Some(movement)));
+    assert!(tt.probe(43, 2).is_none());
+
+    // a mate found 5 plies from the root at ply 2 is a mate in 3 from that position,
+    // reached again at ply 4 it has to come back as mate in 7 from the root
+    tt.store(42, 3, MATE_SCORE - 5, Bound::Exact, None, 2);
+    assert_eq!(tt.probe(42, 4).unwrap().score, MATE_SCORE - 7);
+    assert_eq!(tt.probe(42, 4).unwrap().best_move, Some(movement));
+
+    // a shallower search of another position in the same slot doesn't replace a deeper one
+    let other_key = 42 + tt.capacity() as u64;
+    tt.store(other_key, 1, 0, Bound::Exact, None, 0);
+    assert!(tt.probe(other_key, 0).is_none());
+    // unless the entry is from an older search
+    tt.new_search();
+    tt.store(other_key, 1, 0, Bound::Exact, None, 0);
+    assert!(tt.probe(other_key, 0).is_some());
+
+    tt.clear();
+    assert!(tt.probe(other_key, 0).is_none());
+    tt.resize(2);
+    assert!(tt.capacity() > TranspositionTable::new(1).capacity());
+}
+
+#[test]
+fn test_search_with_transposition_table(){
+    let board = Board::default();
+    let stop = AtomicBool::new(false);
+    let mut tt = TranspositionTable::new(1);
+
+    let first = alpha_beta_search(&board, 3, &stop, &mut tt).unwrap();
+    // the second search hits the root entry stored by the first one
+    let second = alpha_beta_search(&board, 3, &stop, &mut tt).unwrap();
+    assert_eq!(first.best_move, second.best_move);
+    assert_eq!(first.score, second.score);
+    assert!(second.nodes < first.nodes);
+    assert_eq!(tt.probe(board.hash, 0).unwrap().best_move, Some(first.best_move));
+}
+
+// builds a position from a list like "Kg1 Ra1 pf7", uppercase pieces are white
+fn position(pieces: &str, side_to_move: Color) -> Board {
+    let mut board = Board::new();
+    board.castling_rights = 0;
+    board.side_to_move = side_to_move;
+    for piece in pieces.split_whitespace() {
+        let square = Board::str_to_square(&piece[1..]).unwrap();
+        match Board::char_to_piece(piece.chars().next().unwrap()).unwrap() {
+            Piece::King(Color::White) => board.white_king = square as u16,
+            Piece::King(Color::Black) => board.black_king = square as u16,
+            _ => {}
+        }
+        board.set_square(square, Some(Board::char_to_piece(piece.chars().next().unwrap()).unwrap()));
+    }
+    board.hash = board.compute_hash();
+    board
+}
+
+fn relative_eval(board: &Board) -> i32 {
+    if board.side_to_move == Color::White { eval::eval(board) } else { -eval::eval(board) }
+}
+
+// quiescence with a full window gives the exact value of the capture tree, same as the engine leaves
+fn reference_quiescence(board: &Board, ply: i32, mut alpha: i32, beta: i32, nodes: &mut u64) -> i32 {
+    *nodes += 1;
+    let in_check = board.is_check();
+    let moves = if in_check { generate_moves(board) } else { generate_captures(board) };
+    if in_check && moves.is_empty() {
+        return ply - MATE_SCORE;
+    }
+    let mut best = if in_check { -MATE_SCORE - 1 } else { relative_eval(board) };
+    alpha = alpha.max(best);
+    for movement in moves {
+        if alpha >= beta {
+            break;
+        }
+        let mut child = *board;
+        child.make_move(movement);
+        best = best.max(-reference_quiescence(&child, ply + 1, -beta, -alpha, nodes));
+        alpha = alpha.max(best);
+    }
+    best
+}
+
+// plain minimax like the engine used before alpha-beta, to compare against
+fn reference_minimax(board: &Board, depth: u8, ply: i32, nodes: &mut u64) -> i32 {
+    *nodes += 1;
+    if depth == 0 {
+        return reference_quiescence(board, ply, -MATE_SCORE - 1, MATE_SCORE + 1, nodes);
+    }
+    let moves = generate_moves(board);
+    if moves.is_empty() {
+        return if board.is_check() { ply - MATE_SCORE } else { 0 };
+    }
+    let mut best = i32::MIN;
+    for movement in moves {
+        let mut child = *board;
+        child.make_move(movement);
+        best = best.max(-reference_minimax(&child, depth - 1, ply + 1, nodes));
+    }
+    best
+}
+
+fn reference_best_move(board: &Board, depth: u8) -> (Move, i32, u64) {
+    let mut nodes = 0;
+    let mut best = (generate_moves(board)[0], i32::MIN);
+    for movement in generate_moves(board) {
+        let mut child = *board;
+        child.make_move(movement);
+        let score = -reference_minimax(&child, depth - 1, 1, &mut nodes);
+        if score > best.1 {
+            best = (movement, score);
+        }
+    }
+    (best.0, best.1, nodes)
+}
+
+#[test]
+fn test_alpha_beta_matches_minimax(){
+    let positions = [
+        // the white queen can take a hanging rook
+        position("Kg1 Qa1 Pf2 Pg2 Ph2 kg8 ra2 pf7 pg7 ph7", Color::White),
+        // mate in one with the rook
+        position("Kg1 Ra1 Pf2 Pg2 Ph2 kg8 pf7 pg7 ph7", Color::White),
+        // black to move, a knight fork wins the queen
+        position("Ke1 Qb2 Pf2 Pg2 Ph2 kg8 ne5 pf7 pg7 ph7", Color::Black),
+    ];
+    let stop = AtomicBool::new(false);
+
+    for board in positions {
+        let fen = board.to_fen();
+        for depth in 1..=3 {
+            let (expected_move, expected_score, minimax_nodes) = reference_best_move(&board, depth);
+            let mut tt = TranspositionTable::new(1);
+            let result = alpha_beta_search(&board, depth, &stop, &mut tt).unwrap();
+
+            assert_eq!(result.best_move, expected_move, "{} depth {}", fen, depth);
+            assert_eq!(result.score, expected_score, "{} depth {}", fen, depth);
+            assert!(result.nodes <= minimax_nodes);
+        }
+    }
+}
+
+#[test]
+fn test_alpha_beta_prunes(){
+    let board = Board::default();
+    let stop = AtomicBool::new(false);
+    let mut tt = TranspositionTable::new(1);
+
+    let (expected_move, expected_score, minimax_nodes) = reference_best_move(&board, 4);
+    let result = alpha_beta_search(&board, 4, &stop, &mut tt).unwrap();
+
+    assert_eq!(result.best_move, expected_move);
+    assert_eq!(result.score, expected_score);
+    assert!(result.nodes * 10 < minimax_nodes, "{} vs {}", result.nodes, minimax_nodes);
+    assert_eq!(minimax_best_move(&board, 4), Some(expected_move));
+}
+
+#[test]
+fn test_time_budget(){
+    let limits = SearchLimits {
+        time_left: Some(Duration::from_millis(60_030)),
+        increment: Duration::from_millis(1000),
+        moves_to_go: Some(20),
+        ..Default::default()
+    };
+    let (soft, hard) = limits.time_budget().unwrap();
+    assert_eq!(soft, Duration::from_millis(3750));
+    assert_eq!(hard, Duration::from_millis(11250));
+
+    // the last move before the time control can use everything that is left, but never more
+    let limits = SearchLimits { moves_to_go: Some(1), ..limits };
+    let (soft, hard) = limits.time_budget().unwrap();
+    assert_eq!(soft, Duration::from_millis(60_000));
+    assert_eq!(hard, Duration::from_millis(60_000));
+
+    assert_eq!(SearchLimits::depth(5).time_budget(), None);
+    assert_eq!(SearchLimits { infinite: true, ..limits }.time_budget(), None);
+}
+
+#[test]
+fn test_iterative_deepening(){
+    let stop = AtomicBool::new(false);
+    let mut tt = TranspositionTable::new(1);
+
+    let board = Board::default();
+    let mut depths = Vec::new();
+    let result = iterative_deepening(&board, &SearchLimits::move_time(Duration::from_millis(300)), &stop, &mut tt, |depth, _, _| depths.push(depth));
+    assert!(result.is_some());
+    assert_eq!(depths[..3], [1, 2, 3]);
+    // the time budget ends the search long before the depth limit
+    assert!(*depths.last().unwrap() < MAX_DEPTH);
+
+    // stops as soon as it sees the mate instead of going on to the depth limit
+    let board = position("Kg1 Ra1 Pf2 Pg2 Ph2 kg8 pf7 pg7 ph7", Color::White);
+    let mut depths = Vec::new();
+    let result = iterative_deepening(&board, &SearchLimits::depth(6), &stop, &mut tt, |depth, _, _| depths.push(depth)).unwrap();
+    assert!(result.score > MATE_THRESHOLD);
+    assert!(depths.len() < 6);
+
+    // a search stopped before finishing depth 1 still returns a legal move
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    let board = Board::default();
+    let result = iterative_deepening(&board, &SearchLimits::depth(6), &stop, &mut tt, |_, _, _| {}).unwrap();
+    assert!(generate_moves(&board).contains(&result.best_move));
+}
+
+#[test]
+fn test_quiescence(){
+    let stop = AtomicBool::new(false);
+
+    // the knight on d5 is defended by the pawn, taking it with the queen at depth 1 only
+    // looks good if the search stops right after the capture
+    let board = position("Ke1 Qd1 ke8 nd5 pe6", Color::White);
+
+    let mut tt = TranspositionTable::new(1);
+    let result = alpha_beta_search(&board, 1, &stop, &mut tt).unwrap();
+    assert_ne!(result.best_move, Move::string_to_move("d1d5", &board).unwrap());
+
+    let captures = generate_captures(&board);
+    assert_eq!(captures, vec![Move::string_to_move("d1d5", &board).unwrap()]);
+}
+
+// the capture generator gives the same moves as the full list filtered, a couple of plies into
+// every perft position
+#[test]
+fn test_generate_captures(){
+    fn compare(board: &mut Board, depth: u8) {
+        let mut expected = generate_moves(board);
+        expected.retain(|movement| movement.is_capture() || movement.is_promotion());
+        let captures = generate_captures(board);
+        assert_eq!(captures.len(), expected.len(), "{}", board.to_fen());
+        assert!(captures.iter().all(|movement| expected.contains(movement)), "{}", board.to_fen());
+
+        if depth == 0 {
+            return;
+        }
+        for movement in generate_moves(board) {
+            let undo = board.make_move(movement);
+            compare(board, depth - 1);
+            board.unmake_move(movement, undo);
+        }
+    }
+
+    for fen in [
+        START_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    ] {
+        let mut board = Board::from_fen(fen).unwrap();
+        compare(&mut board, 2);
+    }
+}
+
+#[test]
+fn test_move_ordering(){
+    // the pawn on e4 and the queen on d1 can both take something on d5/e5
+    let board = position("Ke1 Qd1 Pe4 Nc3 ke8 qd5 pe5", Color::White);
+    let pawn_takes_queen = Move::string_to_move("e4d5", &board).unwrap();
+    let knight_takes_queen = Move::string_to_move("c3d5", &board).unwrap();
+    let queen_takes_queen = Move::string_to_move("d1d5", &board).unwrap();
+    let quiet = Move::string_to_move("e1f2", &board).unwrap();
+    let other_quiet = Move::string_to_move("e1e2", &board).unwrap();
+
+    let mut ordering = MoveOrdering::new();
+    let mut moves = generate_moves(&board);
+    ordering.order_moves(&board, &mut moves, None, 0);
+    assert_eq!(moves[..3], [pawn_takes_queen, knight_takes_queen, queen_takes_queen]);
+    assert!(mvv_lva(&board, pawn_takes_queen) > mvv_lva(&board, queen_takes_queen));
+
+    // the hash move beats any capture
+    ordering.order_moves(&board, &mut moves, Some(quiet), 0);
+    assert_eq!(moves[0], quiet);
+
+    // a killer goes right after the captures, but only at its own ply
+    ordering.update(Color::White, other_quiet, 3, 2);
+    ordering.order_moves(&board, &mut moves, None, 2);
+    assert_eq!(moves[3], other_quiet);
+    assert!(ordering.score_move(&board, other_quiet, None, 5) < ordering.score_move(&board, other_quiet, None, 2));
+    // and the history remembers it everywhere
+    assert!(ordering.score_move(&board, other_quiet, None, 5) > ordering.score_move(&board, quiet, None, 5));
+
+    // captures never become killers
+    ordering.update(Color::White, queen_takes_queen, 3, 4);
+    ordering.order_moves(&board, &mut moves, None, 4);
+    assert_eq!(moves[..3], [pawn_takes_queen, knight_takes_queen, queen_takes_queen]);
+}
+
+// known counts from https://www.chessprogramming.org/Perft_Results
+#[test]
+fn test_perft() {
+    let board = Board::default();
+    assert_eq!(perft(&board, 0), 1);
+    assert_eq!(perft(&board, 1), 20);
+    assert_eq!(perft(&board, 2), 400);
+    assert_eq!(perft(&board, 3), 8902);
+    assert_eq!(perft(&board, 4), 197281);
+
+    // Kiwipete, castling, en passant and promotions all over the place
+    let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    assert_eq!(perft(&board, 1), 48);
+    assert_eq!(perft(&board, 2), 2039);
+    assert_eq!(perft(&board, 3), 97862);
+
+    // en passant that would leave the king in check along the rank
+    let board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+    assert_eq!(perft(&board, 4), 43238);
+
+    // a king move only loses the castling rights of its own side
+    let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1").unwrap();
+    board.make_move(Move::string_to_move("e8d8", &board).unwrap());
+    assert_eq!(board.castling_rights, 0b1100);
+
+    let board = Board::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1").unwrap();
+    assert_eq!(perft(&board, 3), 9467);
+
+    let board = Board::from_fen("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8").unwrap();
+    assert_eq!(perft(&board, 3), 62379);
+
+    let board = Board::from_fen("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10").unwrap();
+    assert_eq!(perft(&board, 3), 89890);
+}
+
+#[test]
+fn test_divide() {
+    let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    let counts = divide(&board, 2);
+    assert_eq!(counts.len(), 48);
+    assert_eq!(counts.iter().map(|(_, nodes)| nodes).sum::<u64>(), perft(&board, 2));
+
+    let count_for = |movestring: &str| {
+        let movement = Move::string_to_move(movestring, &board).unwrap();
+        counts.iter().find(|(m, _)| *m == movement).unwrap().1
+    };
+    assert_eq!(count_for("e1c1"), 43);
+    assert_eq!(count_for("e1g1"), 43);
+    assert_eq!(count_for("d5e6"), 46);
+
+    assert!(divide(&board, 0).is_empty());
+}
+
+#[test]
+fn test_game_status() {
+    let board = Board::default();
+    assert_eq!(board.status(&[]), GameStatus::Ongoing);
+
+    // fool's mate
+    let board = Board::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+    let outcome = board.status(&[]).outcome().unwrap();
+    assert_eq!(outcome, Outcome { winner: Some(Color::Black), reason: Reason::Checkmate });
+    assert_eq!(outcome.result_string(), "0-1");
+    assert_eq!(outcome.to_string(), "Black wins by checkmate");
+
+    let board = Board::from_fen("7k/5Q2/8/8/8/8/8/7K b - - 0 1").unwrap();
+    assert_eq!(board.status(&[]), GameStatus::Over(Outcome::draw(Reason::Stalemate)));
+
+    // the clocks count half moves
+    let board = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 99 80").unwrap();
+    assert_eq!(board.status(&[]), GameStatus::Ongoing);
+    let board = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 100 80").unwrap();
+    assert_eq!(board.status(&[]), GameStatus::Claimable(Reason::FiftyMoveRule));
+    assert_eq!(board.status(&[]).outcome(), None);
+    let board = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 150 80").unwrap();
+    assert_eq!(board.status(&[]), GameStatus::Over(Outcome::draw(Reason::SeventyFiveMoveRule)));
+    // but mate on the last move still wins
+    let board = Board::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 150 80").unwrap();
+    assert_eq!(board.status(&[]).outcome().unwrap().reason, Reason::Checkmate);
+}
+
+#[test]
+fn test_repetition() {
+    let mut board = Board::default();
+    let mut history = Vec::new();
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        for movestring in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            let movement = Move::string_to_move(movestring, &board).unwrap();
+            history.push(board.hash);
+            board.make_move(movement);
+        }
+        statuses.push(board.status(&history));
+    }
+    assert_eq!(board.repetition_count(&history), 5);
+    assert_eq!(statuses, [
+        GameStatus::Ongoing,
+        GameStatus::Claimable(Reason::ThreefoldRepetition),
+        GameStatus::Claimable(Reason::ThreefoldRepetition),
+        GameStatus::Over(Outcome::draw(Reason::FivefoldRepetition)),
+    ]);
+
+    // a pawn move resets everything
+    let movement = Move::string_to_move("e2e4", &board).unwrap();
+    history.push(board.hash);
+    board.make_move(movement);
+    assert_eq!(board.repetition_count(&history), 1);
+}
+
+#[test]
+fn test_insufficient_material() {
+    for (fen, insufficient) in [
+        ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", true),
+        ("4k3/8/8/8/8/8/8/2N1K3 w - - 0 1", true),
+        ("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+        // bishops on c1 and f8 are both on dark squares
+        ("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+        ("4k3/8/8/8/8/8/8/2BBK3 w - - 0 1", false),
+        ("4kn2/8/8/8/8/8/8/2N1K3 w - - 0 1", false),
+        ("4kn2/8/8/8/8/8/8/2B1K3 w - - 0 1", false),
+        ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", false),
+        ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", false),
+    ] {
+        let board = Board::from_fen(fen).unwrap();
+        assert_eq!(board.is_insufficient_material(), insufficient, "{}", fen);
+        assert_eq!(board.status(&[]).is_over(), insufficient, "{}", fen);
+    }
+}
+
+#[test]
+fn test_bitboards() {
+    let square = |name: &str| Board::str_to_square(name).unwrap();
+    let bitboard = |names: &str| names.split_whitespace().fold(0, |bb, name| bb | square_bb(square(name)));
+
+    assert_eq!(KNIGHT_ATTACKS[square("a1")], bitboard("b3 c2"));
+    assert_eq!(PAWN_ATTACKS[0][square("h2")], bitboard("g3"));
+    assert_eq!(PAWN_ATTACKS[1][square("b7")], bitboard("a6 c6"));
+
+    // sliders stop on the first piece in each direction, whatever its color
+    let occupied = bitboard("a4 c1 d5");
+    assert_eq!(rook_attacks(square("a1"), occupied), bitboard("a2 a3 a4 b1 c1"));
+    assert_eq!(bishop_attacks(square("b3"), occupied), bitboard("a2 c2 d1 a4 c4 d5"));
+    assert_eq!(rook_attacks(square("h8"), 0).count_ones(), 14);
+
+    let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    assert_eq!(board.pieces(Piece::Knight(Color::White)), bitboard("c3 e5"));
+    assert_eq!(board.occupied().count_ones(), 32);
+    assert_eq!(board.attackers(square("e6"), Color::White, board.occupied()), bitboard("d5"));
+
+    // make and unmake keep them in sync with the mailbox for every kind of move
+    let before = board;
+    for movement in generate_moves(&before) {
+        let undo_info = board.make_move(movement);
+        assert_eq!((board.bitboards, board.occupancy), board.compute_bitboards(), "{}", movement.to_uci());
+        board.unmake_move(movement, undo_info);
+        assert_eq!(board, before);
+    }
+}
+
+#[test]
+fn test_legal_movegen() {
+    // en passant that uncovers the king along the rank
+    let board = Board::from_fen("8/8/8/8/k2Pp2Q/8/8/3K4 b - d3 0 1").unwrap();
+    assert!(generate_moves(&board).iter().all(|movement| !movement.is_en_passant()));
+    // en passant capturing the pawn that gives check
+    let board = Board::from_fen("8/8/8/2k5/3Pp3/8/8/3K4 b - d3 0 1").unwrap();
+    assert!(generate_moves(&board).iter().any(|movement| movement.is_en_passant()));
+    // a pinned pawn can only capture along the pin
+    let board = Board::from_fen("4k3/8/8/8/1b6/2P5/3K4/8 w - - 0 1").unwrap();
+    let moves: Vec<String> = generate_moves(&board).iter().filter(|m| m.get_from() == 18).map(|m| m.to_uci()).collect();
+    assert_eq!(moves, ["c3b4"]);
+
+    // perft positions with the tricky ones from https://www.chessprogramming.org/Perft_Results
+    for (fen, depth, nodes) in [
+        ("3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1", 6, 1_134_888),
+        ("8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1", 6, 1_015_133),
+        ("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", 6, 1_440_467),
+        ("r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1", 4, 1_274_206),
+        ("8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1", 5, 1_004_658),
+        ("8/8/2k5/5q2/5n2/8/5K2/8 b - - 0 1", 4, 23_527),
+    ] {
+        let board = Board::from_fen(fen).unwrap();
+        assert_eq!(perft(&board, depth), nodes, "{}", fen);
+    }
+
+    // every generated move passes the slow make/unmake check
+    let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    for movement in generate_moves(&board) {
+        let mut next = board;
+        next.make_move(movement);
+        assert!(generate_moves(&next).iter().all(|&reply| is_valid_move(&next, reply)));
+    }
+}
+
+#[test]
+fn test_game_undo_redo() {
+    let mut game = Game::default();
+    let start = *game.board();
+    for movestring in ["e2e4", "e7e5", "g1f3", "b8c6"] {
+        game.push_str(movestring).unwrap();
+    }
+    assert_eq!(game.ply(), 4);
+    assert_eq!(game.board().fullmove_number, start.fullmove_number + 2);
+    assert!(game.push_str("e1e3").is_err());
+    let after_four = *game.board();
+
+    let b8c6 = game.pop().unwrap();
+    assert_eq!(b8c6.to_uci(), "b8c6");
+    assert_eq!(game.redo_moves(), [b8c6]);
+    assert_eq!(game.redo(), Some(b8c6));
+    assert_eq!(game.redo(), None);
+    assert_eq!(*game.board(), after_four);
+
+    // back to the start and forward again gives the same positions
+    game.jump_to(0).unwrap();
+    assert_eq!(*game.board(), start);
+    assert!(game.history().is_empty());
+    assert_eq!(game.pop(), None);
+    game.jump_to(4).unwrap();
+    assert_eq!(*game.board(), after_four);
+    assert!(game.jump_to(5).is_err());
+
+    // a new move after going back drops the old line
+    game.jump_to(2).unwrap();
+    game.push_str("f1c4").unwrap();
+    assert_eq!(game.moves().iter().map(|m| m.to_uci()).collect::<Vec<_>>(), ["e2e4", "e7e5", "f1c4"]);
+    assert!(game.redo_moves().is_empty());
+    assert_eq!(game.history().len(), 3);
+    assert_eq!(game.history()[0], start.hash);
+    assert_eq!(*game.start_position(), start);
+}
+
+#[test]
+fn test_game_repetition() {
+    let mut game = Game::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+    for movestring in ["e1d1", "e8d8", "d1e1", "d8e8", "e1d1", "e8d8", "d1e1"] {
+        game.push_str(movestring).unwrap();
+        assert_eq!(game.status(), GameStatus::Ongoing);
+    }
+    game.push_str("d8e8").unwrap();
+    assert_eq!(game.status(), GameStatus::Claimable(Reason::ThreefoldRepetition));
+    assert!(!game.status().is_over());
+    game.pop();
+    assert_eq!(game.status(), GameStatus::Ongoing);
+}
+
+#[test]
+fn test_make_move_state() {
+    let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 5 10").unwrap();
+    let start = board;
+
+    let castle = Move::string_to_move("e1g1", &board).unwrap();
+    let undo_castle = board.make_move(castle);
+    assert_eq!(board.side_to_move, Color::Black);
+    assert_eq!(board.fullmove_number, 10);
+    assert_eq!(board.halfmove_clock, 6);
+    assert_eq!(board.king_square(Color::White), Board::str_to_square("g1").unwrap());
+    assert_eq!(board.hash, board.compute_hash());
+
+    let rook_takes = Move::string_to_move("a8a1", &board).unwrap();
+    let after_castle = board;
+    let undo_capture = board.make_move(rook_takes);
+    assert_eq!(board.side_to_move, Color::White);
+    assert_eq!(board.fullmove_number, 11);
+    assert_eq!(board.halfmove_clock, 0);
+    assert_eq!(board.to_fen(), "4k2r/8/8/8/8/8/8/r4RK1 w k - 0 11");
+
+    board.unmake_move(rook_takes, undo_capture);
+    assert_eq!(board, after_castle);
+    board.unmake_move(castle, undo_castle);
+    assert_eq!(board, start);
+}
+
+#[test]
+fn test_chess960() {
+    // published Chess960 perft results
+    let positions = [
+        ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 326672),
+        ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", 667366),
+    ];
+    for (fen, nodes) in positions {
+        let board = Board::from_fen(fen).unwrap();
+        assert!(board.chess960);
+        assert_eq!(perft(&board, 4), nodes, "{}", fen);
+    }
+
+    // 518 is the normal starting position
+    let standard = Board::from_chess960_index(518).unwrap();
+    assert_eq!(standard.squares, Board::default().squares);
+    assert_eq!(standard.castling_rooks, Board::default().castling_rooks);
+    assert!(Board::from_chess960_index(960).is_err());
+    let position = Board::from_chess960_index(0).unwrap();
+    assert_eq!(position.to_fen(), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+    assert_eq!(position.to_shredder_fen(), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1");
+    assert_eq!(perft(&position, 1), 20);
+
+    // X-FEN needs the file letter when another rook stands further out
+    let fen = "4k3/8/8/8/8/8/8/RR2K3 w B - 0 1";
+    let board = Board::from_fen(fen).unwrap();
+    assert_eq!(board.castling_rooks[1], 1);
+    assert_eq!(board.to_fen(), fen);
+    assert_eq!(Board::from_fen(&board.to_shredder_fen()).unwrap(), board);
+    assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 w K - 0 1").is_err());
+
+    // the king and the rook swap squares
+    let mut board = Board::from_fen("4k3/8/8/8/8/8/8/5KR1 w G - 0 1").unwrap();
+    let start = board;
+    let castle = Move::string_to_move("f1g1", &board).unwrap();
+    assert!(castle.is_castle_kingside());
+    assert_eq!(castle.to_uci_chess960(), "f1g1");
+    let undo = board.make_move(castle);
+    assert_eq!(board.to_fen(), "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+    assert_eq!(board.hash, board.compute_hash());
+    assert_eq!(board.king_square(Color::White), 6);
+    board.unmake_move(castle, undo);
+    assert_eq!(board, start);
+
+    // in normal chess castling is still written e1g1 for UCI, UCI_Chess960 uses e1h1
+    let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    let castle = Move::string_to_move("e1g1", &board).unwrap();
+    assert_eq!(Move::string_to_move("e1h1", &board).unwrap(), castle);
+    assert_eq!(castle.to_uci(), "e1g1");
+    assert_eq!(castle.to_uci_chess960(), "e1h1");
+    let board = Uci::parse_position_chess960(&["startpos", "moves", "g1f3", "g8f6", "e2e3", "e7e6", "f1e2", "f8e7", "e1h1"], true).unwrap();
+    assert_eq!(board.king_square(Color::White), 6);
+}
+
+#[test]
+fn test_fen_errors() {
+    let cases = [
+        ("8/8/8/8/8/8/8/8 w - -", FenError::FieldCount { found: 4 }),
+        ("4k3/8/8/8/8/8/8 w - - 0 1", FenError::BadRank { rank: 1, offset: 15 }),
+        ("4k3/8/8/8/8/8/8/4K4 w - - 0 1", FenError::BadRank { rank: 1, offset: 18 }),
+        ("4k3/8/8/8/8/8/8/4X3 w - - 0 1", FenError::BadPiece { ch: 'X', offset: 17 }),
+        ("4k3/8/8/8/8/8/8/4K3 x - - 0 1", FenError::BadSideToMove { offset: 20 }),
+        ("4k3/8/8/8/8/8/8/4K3 w KX - 0 1", FenError::BadCastling { ch: 'K', offset: 22 }),
+        ("4k3/8/8/8/8/8/8/4K2R w KX - 0 1", FenError::BadCastling { ch: 'X', offset: 24 }),
+        ("4k3/8/8/8/8/8/8/4K3 w - e4 0 1", FenError::BadEnPassant { offset: 24 }),
+        ("4k3/8/8/8/8/8/8/4K3 w - - x 1", FenError::BadHalfmoveClock { offset: 26 }),
+        ("4k3/8/8/8/8/8/8/4K3 w - - 0 -1", FenError::BadFullmoveNumber { offset: 28 }),
+        ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::MissingKing { color: Color::Black }),
+        ("4k3/8/8/8/8/8/8/4K1K1 w - - 0 1", FenError::ExtraKing { color: Color::White, offset: 19 }),
+        ("4k3/8/8/8/8/8/8/P3K3 w - - 0 1", FenError::PawnOnBackRank { offset: 16 }),
+        ("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1", FenError::OpponentInCheck { offset: 22 }),
+    ];
+    for (fen, error) in cases {
+        assert_eq!(Board::from_fen(fen), Err(error), "{}", fen);
+    }
+
+    let error = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - e4 0 1").unwrap_err();
+    assert_eq!(error.field(), FenField::EnPassant);
+    assert_eq!(error.to_string(), "invalid en passant square at 24");
+    let boxed: Box<dyn std::error::Error> = Box::new(error);
+    assert!(!boxed.to_string().is_empty());
+}
+
+#[test]
+fn test_validate() {
+    assert!(Board::default().validate().is_empty());
+    assert!(Board::from_chess960_index(0).unwrap().validate().is_empty());
+
+    // a board put together square by square, like an editor does
+    let mut board = Board::default();
+    board.set_square(4, None);
+    board.set_square(60, Some(Piece::King(Color::White)));
+    board.set_square(0, Some(Piece::Pawn(Color::White)));
+    board.set_square(7, None);
+    assert_eq!(board.validate(), vec![
+        PositionError::MissingKing(Color::Black),
+        PositionError::PawnOnBackRank(0),
+        PositionError::CastlingWithoutKing(0b1000),
+        PositionError::CastlingWithoutRook(0b1000),
+        PositionError::CastlingWithoutKing(0b0100),
+        PositionError::CastlingWithoutRook(0b0100),
+        PositionError::CastlingWithoutKing(0b0010),
+        PositionError::CastlingWithoutKing(0b0001),
+    ]);
+
+    // black to move with the white king attacked
+    let mut board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+    board.set_square(12, Some(Piece::Queen(Color::Black)));
+    assert_eq!(board.validate(), vec![PositionError::OpponentInCheck]);
+    board.set_square(0, Some(Piece::King(Color::White)));
+    assert_eq!(board.validate(), vec![PositionError::TooManyKings(Color::White)]);
+
+    // normal parsing takes these, the strict mode doesn't
+    let fen = "4k3/8/8/8/8/8/8/4K3 w - e6 0 1";
+    assert!(Board::from_fen(fen).is_ok());
+    assert_eq!(Board::from_fen_strict(fen).unwrap_err(), FenError::IllegalPosition { problems: vec![PositionError::BadEnPassant(44)] });
+    assert!(Board::from_fen_strict("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2").is_ok());
+    assert!(Board::from_fen_strict("4k3/8/8/8/8/8/8/4K3 w - - 0 1").is_ok());
+    assert!(Board::from_fen_strict("8/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+    let error = Board::from_fen_strict("4k3/8/8/8/8/8/8/4K3 w - e6 0 1").unwrap_err();
+    assert_eq!(error.to_string(), "illegal position: en passant square e6 without a pawn that just moved two squares");
+}
+
+#[test]
+fn test_san_parsing() {
+    let board = Board::default();
+    assert_eq!(Move::from_san("Nf3", &board).unwrap().to_uci(), "g1f3");
+    assert_eq!(Move::from_san("e4", &board).unwrap().to_uci(), "e2e4");
+    assert_eq!(Move::string_to_move("Nc3", &board).unwrap().to_uci(), "b1c3");
+    assert!(Move::from_san("e5", &board).is_err());
+    assert!(Move::from_san("Nf4", &board).is_err());
+    assert!(Move::from_san("Zf3", &board).is_err());
+    assert!(Move::from_san("", &board).is_err());
+
+    let board = Board::from_fen("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2").unwrap();
+    assert_eq!(Move::from_san("exd5", &board).unwrap().to_uci(), "e4d5");
+    assert_eq!(Move::from_san("e5", &board).unwrap().to_uci(), "e4e5");
+    // a pawn capture has to name its file
+    assert!(Move::from_san("d5", &board).is_err());
+    assert!(Move::from_san("Nxf3", &board).unwrap_err().contains("doesn't capture"));
+    assert!(Move::from_san("exd6", &board).is_err());
+
+    // disambiguation by file, by rank and by both
+    let board = Board::from_fen("4k3/8/8/8/1N3N2/8/1N6/4K3 w - - 0 1").unwrap();
+    assert!(Move::from_san("Nd3", &board).unwrap_err().contains("ambiguous"));
+    assert_eq!(Move::from_san("Nfd3", &board).unwrap().to_uci(), "f4d3");
+    assert!(Move::from_san("Nbd3", &board).unwrap_err().contains("ambiguous"));
+    assert_eq!(Move::from_san("N2d3", &board).unwrap().to_uci(), "b2d3");
+    assert_eq!(Move::from_san("Nb4d3", &board).unwrap().to_uci(), "b4d3");
+    assert!(Move::from_san("Nfxd3", &board).unwrap_err().contains("doesn't capture"));
+
+    // castling, promotion and suffixes
+    let board = Board::from_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    assert_eq!(Move::from_san("O-O", &board).unwrap().to_uci(), "e1g1");
+    assert_eq!(Move::from_san("0-0-0", &board).unwrap().to_uci(), "e1c1");
+    assert_eq!(Move::string_to_move("O-O-O+", &board).unwrap().to_uci(), "e1c1");
+    assert_eq!(Move::from_san("b8=Q", &board).unwrap().to_uci(), "b7b8q");
+    assert_eq!(Move::from_san("b8N!?", &board).unwrap().to_uci(), "b7b8n");
+    assert_eq!(Move::from_san("bxa8=R+", &board).unwrap().to_uci(), "b7a8r");
+    assert_eq!(Move::from_san("Rxa8#", &board).unwrap().to_uci(), "a1a8");
+    assert!(Move::from_san("b8", &board).is_err());
+    let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K1NR w KQkq - 0 1").unwrap();
+    assert!(Move::from_san("O-O", &board).is_err());
+}
+
+// every move of the tree written in SAN and LAN has to parse back to itself, and no two moves
+// of a position can share their SAN
+fn san_round_trip(board: &Board, depth: u8) -> usize {
+    if depth == 0 {
+        return 0;
+    }
+    let moves = generate_moves(board);
+    let mut written: Vec<String> = Vec::new();
+    let mut count = 0;
+    for movement in moves {
+        for notation in [Notation::San, Notation::Lan, Notation::Uci] {
+            let text = movement.to_notation(board, notation);
+            assert_eq!(Move::string_to_move(&text, board), Ok(movement), "{} in {}", text, board.to_fen());
+        }
+        let san = movement.to_san(board);
+        assert!(!written.contains(&san), "{} twice in {}", san, board.to_fen());
+        written.push(san);
+
+        let mut next = *board;
+        next.make_move(movement);
+        count += 1 + san_round_trip(&next, depth - 1);
+    }
+    count
+}
+
+#[test]
+fn test_san_writing() {
+    let board = Board::default();
+    assert_eq!(Move::string_to_move("g1f3", &board).unwrap().to_san(&board), "Nf3");
+    assert_eq!(Move::string_to_move("g1f3", &board).unwrap().to_lan(&board), "Ng1-f3");
+    assert_eq!(Move::string_to_move("e2e4", &board).unwrap().to_san(&board), "e4");
+
+    let cases = [
+        ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1", "O-O", "O-O"),
+        ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1c1", "O-O-O", "O-O-O"),
+        ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "a1a8", "Rxa8+", "Ra1xa8+"),
+        ("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q", "a8=Q", "a7-a8=Q"),
+        ("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8n", "axb8=N", "a7xb8=N"),
+        ("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q", "axb8=Q+", "a7xb8=Q+"),
+        ("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5f6", "exf6", "e5xf6"),
+        ("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1a8", "Ra8#", "Ra1-a8#"),
+        ("4k3/8/8/8/1N3N2/8/1N6/4K3 w - - 0 1", "f4d3", "Nfd3", "Nf4-d3"),
+        ("4k3/8/8/8/1N3N2/8/1N6/4K3 w - - 0 1", "b2d3", "N2d3", "Nb2-d3"),
+        ("4k3/8/8/8/1N3N2/8/1N6/4K3 w - - 0 1", "b4d3", "Nb4d3", "Nb4-d3"),
+        ("4k3/8/8/8/8/8/R6R/4K3 w - - 0 1", "a2d2", "Rad2", "Ra2-d2"),
+        ("4k3/R7/8/8/8/8/R7/4K3 w - - 0 1", "a2a5", "R2a5", "Ra2-a5"),
+    ];
+    for (fen, uci, san, lan) in cases {
+        let board = Board::from_fen(fen).unwrap();
+        let movement = Move::string_to_move(uci, &board).unwrap();
+        assert_eq!(movement.to_san(&board), san, "{}", fen);
+        assert_eq!(movement.to_lan(&board), lan, "{}", fen);
+        assert_eq!(movement.to_notation(&board, Notation::Uci), uci);
+    }
+
+    let positions = [
+        ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 3),
+        ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 2),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 3),
+        ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 2),
+        ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 2),
+        ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 2),
+    ];
+    for (fen, depth) in positions {
+        assert!(san_round_trip(&Board::from_fen(fen).unwrap(), depth) > 0);
+    }
+}
+
+#[test]
+fn test_pgn_export() {
+    let mut game = Game::default();
+    for san in ["e4", "e5", "Bc4", "Nc6", "Qh5", "Nf6", "Qxf7#"] {
+        game.push_str(san).unwrap();
+    }
+    let mut pgn = PgnGame::from_game(&game);
+    pgn.set_tag("White", "Player");
+    pgn.set_tag("Annotator", "me");
+    pgn.nodes[3].comments.push("the bishop eyes f7".to_string());
+    assert_eq!(pgn.to_pgn(), "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n[White \"Player\"]\n\
+        [Black \"?\"]\n[Result \"1-0\"]\n[Annotator \"me\"]\n\n\
+        1. e4 e5 2. Bc4 {the bishop eyes f7} 2... Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n\n");
+
+    // a custom start gets SetUp and FEN, black moves first
+    let game = {
+        let mut game = Game::from_fen("4k3/8/8/8/8/8/4p3/4K3 b - - 0 40").unwrap();
+        game.push_str("Kd7").unwrap();
+        game
+    };
+    let text = PgnGame::from_game(&game).to_pgn();
+    assert!(text.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4p3/4K3 b - - 0 40\"]\n"));
+    assert!(text.ends_with("\n40... Kd7 *\n\n"));
+
+    // a long game wraps at 80 columns
+    let mut game = Game::default();
+    for _ in 0..10 {
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            game.push_str(uci).unwrap();
+        }
+    }
+    let text = PgnGame::from_game(&game).to_pgn();
+    let movetext: Vec<&str> = text.split("\n\n").nth(1).unwrap().lines().collect();
+    assert!(movetext.len() > 1);
+    assert!(movetext.iter().all(|line| line.len() <= 80 && !line.ends_with('.')));
+}
+
+#[test]
+fn test_pgn_import() {
+    let text = r#"[Event "Test"]
+[White "A"]
+[Black "B \"quoted\""]
+[Result "1-0"]
+
+{Opening comment} 1. e4 $1 e5 (1... c5 {Sicilian} 2. Nf3 (2. Nc3) d6) 2. Nf3!? Nc6
+; rest of line comment
+3. Bb5 {[%clk 0:01:30] [%eval 0.35] Spanish} a6 {[%eval #-3]} 4.Ba4 1-0
+
+[Event "Broken"]
+
+1. e4 e5 2. Ke3 Nc6 *
+
+[Event "Third"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/8/4K2R w K - 0 1"]
+
+1. O-O Kd7 *
+"#;
+    let mut reader = PgnReader::new(text.as_bytes());
+
+    let game = reader.next().unwrap().unwrap();
+    assert_eq!(game.tag("Event"), Some("Test"));
+    assert_eq!(game.tag("Black"), Some("B \"quoted\""));
+    assert_eq!(game.result, "1-0");
+    assert_eq!(game.nodes[0].comments, ["Opening comment"]);
+    let mainline = game.mainline();
+    let sans: Vec<String> = game.to_game().moves().iter().map(|m| m.to_uci()).collect();
+    assert_eq!(sans, ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "b5a4"]);
+    assert_eq!(game.nodes[mainline[0]].nags, [1]);
+    assert_eq!(game.nodes[mainline[2]].nags, [5]);
+    assert_eq!(game.nodes[mainline[3]].comments, ["rest of line comment"]);
+    let bb5 = &game.nodes[mainline[4]];
+    assert_eq!(bb5.comments, ["Spanish"]);
+    assert_eq!(bb5.clock, Some(Duration::from_secs(90)));
+    assert_eq!(bb5.eval, Some(PgnEval::Centipawns(35)));
+    assert_eq!(game.nodes[mainline[5]].eval, Some(PgnEval::Mate(-3)));
+
+    // clocks that aren't a time are ignored instead of panicking
+    for clock in ["inf", "NaN", "-1:00:00", "0:-5:00", "1e300:00:00"] {
+        let text = format!("1. e4 {{[%clk {}] [%eval 0.2]}} *", clock);
+        let game = PgnReader::new(text.as_bytes()).next().unwrap().unwrap();
+        let e4 = &game.nodes[game.mainline()[0]];
+        assert_eq!(e4.clock, None, "{}", clock);
+        assert_eq!(e4.eval, Some(PgnEval::Centipawns(20)));
+    }
+
+    // 1... c5 is a variation of 1... e5, with its own variation 2. Nc3 inside
+    let e4 = &game.nodes[mainline[0]];
+    assert_eq!(e4.children.len(), 2);
+    let c5 = &game.nodes[e4.children[1]];
+    assert_eq!(c5.movement.unwrap().to_uci(), "c7c5");
+    assert_eq!(c5.comments, ["Sicilian"]);
+    assert_eq!(game.nodes[c5.children[0]].children.len(), 1);
+    let nf3 = &game.nodes[c5.children[0]];
+    assert_eq!(game.nodes[nf3.children[0]].movement.unwrap().to_uci(), "d7d6");
+    assert_eq!(c5.children.len(), 2);
+    assert_eq!(game.nodes[c5.children[1]].movement.unwrap().to_uci(), "b1c3");
+
+    // the illegal king move, with where it is, then the reader goes on
+    let error = reader.next().unwrap().unwrap_err();
+    assert_eq!((error.line, error.column), (12, 13));
+    let game = reader.next().unwrap().unwrap();
+    assert_eq!(game.tag("Event"), Some("Third"));
+    assert_eq!(game.to_game().board().to_fen(), "8/3k4/8/8/8/8/8/5RK1 w - - 2 2");
+    assert!(reader.next().is_none());
+
+    // writing and reading again gives the same tree
+    let (games, errors) = read_all(text);
+    assert_eq!((games.len(), errors.len()), (2, 1));
+    for game in games {
+        let (again, errors) = read_all(&game.to_pgn());
+        assert!(errors.is_empty());
+        assert_eq!(again[0].nodes, game.nodes);
+        assert_eq!(again[0].start, game.start);
+    }
+
+    for (broken, line, column) in [
+        ("1. e4 {never closed", 1, 7),
+        ("1. e4 (1. d4", 1, 7),
+        ("1. e4 e5)", 1, 9),
+        ("[Event \"x\"\n1. e4", 1, 1),
+        ("1. e4 $1 ( $2 e5)", 1, 12),
+        ("[FEN \"8/8/8 w - - 0 1\"]\n1. e4", 1, 1),
+    ] {
+        let (games, errors) = read_all(broken);
+        assert!(games.is_empty(), "{}", broken);
+        assert_eq!((errors[0].line, errors[0].column), (line, column), "{}: {}", broken, errors[0]);
+    }
+}
+
+#[test]
+fn test_square_mapping() {
+    assert_eq!(Board::from_fen(START_FEN).unwrap(), Board::default());
+    assert_eq!(Board::default().to_fen(), START_FEN);
+    assert_eq!(Board::str_to_square("a1"), Ok(0));
+    assert_eq!(Board::str_to_square("h1"), Ok(7));
+    assert_eq!(Board::str_to_square("a8"), Ok(56));
+    assert_eq!(Board::str_to_square("e4"), Ok(square_at(4, 3)));
+    assert!(Board::str_to_square("e").is_err());
+    assert!(Board::str_to_square("e44").is_err());
+    for square in 0..64 {
+        assert_eq!(Board::str_to_square(&Board::square_to_string(square as u8)), Ok(square));
+        assert_eq!(square_at(file_of(square), rank_of(square)), square);
+    }
+
+    // FEN in, FEN out, for positions that aren't symmetric
+    for fen in [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+        "4k3/8/8/8/8/8/4p3/4K3 b - - 3 40",
+    ] {
+        assert_eq!(Board::from_fen(fen).unwrap().to_fen(), fen);
+    }
+
+    // a position from FEN plays the same moves as one reached from the start
+    let mut played = Board::default();
+    for uci in ["e2e4", "c7c5"] {
+        played.make_move(Move::string_to_move(uci, &played).unwrap());
+    }
+    let loaded = Board::from_fen("rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2").unwrap();
+    assert_eq!(loaded, played);
+    assert_eq!(loaded.squares[Board::str_to_square("e4").unwrap()], Some(Piece::Pawn(Color::White)));
+    assert_eq!(loaded.squares[Board::str_to_square("c5").unwrap()], Some(Piece::Pawn(Color::Black)));
+    let mut sans: Vec<String> = generate_moves(&loaded).iter().map(|m| m.to_san(&loaded)).collect();
+    sans.sort();
+    assert!(sans.contains(&"Nf3".to_string()) && sans.contains(&"Qh5".to_string()) && sans.contains(&"e5".to_string()));
+    assert!(!sans.contains(&"e4".to_string()));
+    assert_eq!(perft(&loaded, 3), perft(&played, 3));
+}
+
+#[test]
+fn test_epd() {
+    let line = r#"2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";"#;
+    let epd = Epd::parse(line).unwrap();
+    assert_eq!(epd.id(), Some("WAC.001"));
+    assert_eq!(epd.best_moves.iter().map(|m| m.to_uci()).collect::<Vec<_>>(), ["g3g6"]);
+    assert_eq!(epd.board.to_fen(), "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1");
+    assert_eq!(epd.to_epd(), line);
+
+    let line = r#"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 am e5 c5; bm c6 e6; ce -25; pv e6 d4 d5; c0 "French; or Caro-Kann"; hmvc 0; fmvn 1; foo bar;"#;
+    let epd = Epd::parse(line).unwrap();
+    assert_eq!(epd.avoid_moves.len(), 2);
+    assert_eq!(epd.best_moves.iter().map(|m| m.to_uci()).collect::<Vec<_>>(), ["c7c6", "e7e6"]);
+    assert_eq!(epd.centipawns(), Some(-25));
+    assert_eq!(epd.pv().iter().map(|m| m.to_uci()).collect::<Vec<_>>(), ["e7e6", "d2d4", "d7d5"]);
+    assert_eq!(epd.comment(0), Some("French; or Caro-Kann"));
+    assert_eq!(epd.operands("foo"), Some(&["bar".to_string()][..]));
+    assert_eq!(epd.to_epd(), line);
+
+    // the clocks come from hmvc and fmvn
+    let epd = Epd::parse("4k3/8/8/8/8/8/8/4K2R w K - dm 3; hmvc 12; fmvn 40;").unwrap();
+    assert_eq!((epd.board.halfmove_clock, epd.board.fullmove_number), (12, 40));
+    assert_eq!(epd.direct_mate(), Some(3));
+    let mut epd = Epd::parse("4k3/8/8/8/8/8/8/4K2R w K -").unwrap();
+    assert!(epd.operations.is_empty());
+    epd.set_operation("bm", vec!["O-O".to_string()]).unwrap();
+    assert!(epd.set_operation("am", vec!["Kd3".to_string()]).is_err());
+    epd.set_operation("id", vec!["castle".to_string()]).unwrap();
+    assert_eq!(epd.to_epd(), r#"4k3/8/8/8/8/8/8/4K2R w K - bm O-O; id "castle";"#);
+
+    for (line, error) in [
+        ("4k3/8/8/8/8/8/8/4K3 w -", FenError::FieldCount { found: 3 }),
+        ("4k3/8/8/8/8/8/8/4X3 w - - bm Kd1;", FenError::BadPiece { ch: 'X', offset: 17 }),
+        ("4k3/8/8/8/8/8/8/4K3 w - - bm Ke3;", FenError::BadOperand { opcode: "bm".to_string(), offset: 26 }),
+        ("4k3/8/8/8/8/8/8/4K3 w - - id \"x\"", FenError::BadOperand { opcode: "id".to_string(), offset: 26 }),
+        ("4k3/8/8/8/8/8/8/4K3 w - - 3bm Kd1;", FenError::BadOpcode { offset: 26 }),
+        ("4k3/8/8/8/8/8/8/4K3 w - - ce x;", FenError::BadOperand { opcode: "ce".to_string(), offset: 26 }),
+        ("4k3/8/8/8/8/8/8/4K3 w - - hmvc -1;", FenError::BadOperand { opcode: "hmvc".to_string(), offset: 26 }),
+    ] {
+        assert_eq!(Epd::parse(line), Err(error), "{}", line);
+    }
+}
+
+#[test]
+fn test_testsuite() {
+    let positions: Vec<Epd> = [
+        r#"6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - bm Ra8#; id "back rank";"#,
+        r#"6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - am Ra8#; id "avoid";"#,
+        r#"r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - dm 1; id "mate";"#,
+        r#"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - id "nothing to check";"#,
+    ].iter().map(|line| Epd::parse(line).unwrap()).collect();
+
+    let mut tt = TranspositionTable::default();
+    let mut reported = 0;
+    let results = testsuite::run_suite(&positions, &SearchLimits::depth(3), &mut tt, |_| reported += 1);
+    assert_eq!(reported, 4);
+    assert_eq!(results.iter().map(|r| r.solved).collect::<Vec<_>>(), [true, false, true, false]);
+    assert_eq!(results[0].found, "Ra8#");
+    assert_eq!(results[2].found, "Qxf7#");
+    assert!(results[0].solved_at.is_some() && results[1].solved_at.is_none());
+
+    // a mate in one isn't a mate in two
+    assert!(testsuite::is_solution(&positions[2], Move::from_san("Qxf7#", &positions[2].board).unwrap(), MATE_SCORE - 1));
+    assert!(!testsuite::is_solution(&positions[2], Move::from_san("Qxf7#", &positions[2].board).unwrap(), MATE_SCORE - 3));
+
+    let summary = testsuite::summarize(&results);
+    assert_eq!((summary.positions, summary.solved), (4, 2));
+    assert_eq!(summary.nodes, results.iter().map(|r| r.nodes).sum::<u64>());
+
+    let json = testsuite::to_json(&results, &summary);
+    assert!(json.starts_with(r#"{"positions":[{"id":"back rank","expected":"bm Ra8#","found":"Ra8#","score":"mate 1","depth":1,"solved":true,"#));
+    assert!(json.contains(r#""summary":{"positions":4,"solved":2,"#));
+}
+
+#[test]
+fn test_polyglot_book() {
+    // the keys from the Polyglot format description, after playing the moves from the start position
+    for (moves, expected) in [
+        ("", 0x463b96181691fc9c),
+        ("e2e4", 0x823c9b50fd114196),
+        ("e2e4 d7d5", 0x0756b94461c50fb0),
+        ("e2e4 d7d5 e4e5", 0x662fafb965db29d4),
+        ("e2e4 d7d5 e4e5 f7f5", 0x22a48b5a8e47ff78), // e5 can take on f6
+        ("e2e4 d7d5 e4e5 f7f5 e1e2", 0x652a607ca3f242c1), // white loses both castling rights
+        ("e2e4 d7d5 e4e5 f7f5 e1e2 e8f7", 0x00fdd303c946bdd9),
+        ("a2a4 b7b5 h2h4 b5b4 c2c4", 0x3c8123ea7b067637), // b4 can take on c3
+        ("a2a4 b7b5 h2h4 b5b4 c2c4 b4c3 a1a3", 0x5c3f9b829b279560), // only the queenside right goes
+    ] {
+        let mut board = Board::default();
+        for movestring in moves.split_whitespace() {
+            board.make_move(Move::string_to_move(movestring, &board).unwrap());
+        }
+        assert_eq!(board.polyglot_key(), expected, "{}", moves);
+    }
+
+    // en passant only counts when a pawn can take
+    let after_e4 = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+    let no_ep = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+    assert_eq!(after_e4.polyglot_key(), no_ep.polyglot_key());
+    let can_take = Board::from_fen("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap();
+    let cant_take = Board::from_fen("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3").unwrap();
+    assert_ne!(can_take.polyglot_key(), cant_take.polyglot_key());
+    assert_ne!(Board::default().polyglot_key(), Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kkq - 0 1").unwrap().polyglot_key());
+
+    // castling is king takes rook, promotions keep their piece
+    let board = Board::from_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    for uci in ["e1h1", "e1a1", "b7b8n", "b7a8q"] {
+        let movement = Move::string_to_move(uci, &board).unwrap();
+        assert_eq!(decode_move(encode_move(movement), &board), Some(movement), "{}", uci);
+    }
+    assert_eq!(encode_move(Move::string_to_move("e1h1", &board).unwrap()), 4 << 6 | 7);
+
+    let start = Board::default();
+    let key = start.polyglot_key();
+    let e4 = Move::string_to_move("e2e4", &start).unwrap();
+    let d4 = Move::string_to_move("d2d4", &start).unwrap();
+    let mut entries = [
+        BookEntry { key, movement: encode_move(e4), weight: 3, learn: 0 },
+        BookEntry { key, movement: encode_move(d4), weight: 1, learn: 0 },
+        BookEntry { key: key.wrapping_add(1), movement: encode_move(d4), weight: 1, learn: 0 },
+    ];
+    entries.sort_by_key(|entry| entry.key);
+    let bytes: Vec<u8> = entries.iter().flat_map(|entry| entry.to_bytes()).collect();
+    assert_eq!(BookEntry::from_bytes(&entries[0].to_bytes()), entries[0]);
+
+    let mut book = OpeningBook::from_bytes(&bytes).unwrap();
+    assert_eq!(book.len(), 3);
+    assert_eq!(book.moves(&start), [(e4, 3), (d4, 1)]);
+    assert_eq!(book.moves(&after_e4), []);
+    assert_eq!(book.pick(&after_e4), None);
+    for _ in 0..20 {
+        assert!([Some(e4), Some(d4)].contains(&book.pick(&start)));
+    }
+    book.mode = BookMode::Best;
+    assert_eq!(book.pick(&start), Some(e4));
+    book.max_depth = 0;
+    assert_eq!(book.pick(&start), None);
+
+    assert!(OpeningBook::from_bytes(&bytes[1..]).is_err());
+    let unsorted: Vec<u8> = entries.iter().rev().flat_map(|entry| entry.to_bytes()).collect();
+    assert!(OpeningBook::from_bytes(&unsorted).is_err());
+}
+
+#[test]
+fn test_book_builder() {
+    let pgn = r#"[Result "1-0"]
+
+1. e4 e5 2. Nf3 (2. Bc4) Nc6 1-0
+
+[Result "1/2-1/2"]
+
+1. e4 c5 2. Nf3 1/2-1/2
+
+[Result "0-1"]
+
+1. d4 d5 0-1
+
+[Result "*"]
+
+1. e4 e5 *
+
+[Result "1-0"]
+
+1. e4 Qh4 {broken} 2. Kxh4 1-0
+"#;
+    let mut builder = BookBuilder::new();
+    let (games, errors) = builder.add_pgn(pgn.as_bytes());
+    assert_eq!((games, errors.len()), (4, 1));
+
+    let start = Board::default();
+    let e4 = Move::from_san("e4", &start).unwrap();
+    let d4 = Move::from_san("d4", &start).unwrap();
+    assert_eq!(builder.stats(&start, e4), Some(MoveStats { games: 3, white_wins: 1, draws: 1, black_wins: 0 }));
+    assert_eq!(builder.stats(&start, d4).unwrap().score(Color::White), 0);
+    let mut after_e4 = start;
+    after_e4.make_move(e4);
+    let e5 = Move::from_san("e5", &after_e4).unwrap();
+    assert_eq!(builder.stats(&after_e4, e5).unwrap().score(Color::Black), 0);
+    // variations aren't games
+    let mut after_e5 = after_e4;
+    after_e5.make_move(e5);
+    assert_eq!(builder.stats(&after_e5, Move::from_san("Bc4", &after_e5).unwrap()), None);
+
+    // d4 only lost, it isn't written, e4 scored 2 + 1
+    let mut bytes = Vec::new();
+    let written = builder.write(&mut bytes).unwrap();
+    assert_eq!(written * 16, bytes.len());
+    let book = OpeningBook::from_bytes(&bytes).unwrap();
+    assert_eq!(book.moves(&start), [(e4, 3)]);
+    assert_eq!(book.moves(&after_e4).len(), 1); // c5 drew, e5 lost and didn't count
+
+    builder.min_games = 2;
+    assert_eq!(builder.entries().len(), 1);
+
+    let mut white = BookBuilder::new();
+    white.color = Some(Color::White);
+    white.max_ply = 1;
+    white.add_pgn(pgn.as_bytes());
+    let book = OpeningBook::from_bytes(&white.entries().iter().flat_map(|entry| entry.to_bytes()).collect::<Vec<u8>>()).unwrap();
+    assert_eq!(book.moves(&start), [(e4, 3)]);
+    assert!(book.moves(&after_e4).is_empty());
+    assert_eq!(book.len(), 1);
+
+    // the written entries carry the keys from the Polyglot format description, so other
+    // programs find the positions
+    let mut builder = BookBuilder::new();
+    builder.add_pgn("1. e4 d5 2. e5 f5 3. Ke2 Kf7 1/2-1/2".as_bytes());
+    let mut bytes = Vec::new();
+    builder.write(&mut bytes).unwrap();
+    let mut keys: Vec<u64> = bytes.chunks(16).map(|entry| u64::from_be_bytes(entry[..8].try_into().unwrap())).collect();
+    keys.sort();
+    let mut expected = [
+        0x463b96181691fc9c, 0x823c9b50fd114196, 0x0756b94461c50fb0,
+        0x662fafb965db29d4, 0x22a48b5a8e47ff78, 0x652a607ca3f242c1,
+    ];
+    expected.sort();
+    assert_eq!(keys, expected);
+    let book = OpeningBook::from_bytes(&bytes).unwrap();
+    assert_eq!(book.moves(&start), [(e4, 1)]);
+}